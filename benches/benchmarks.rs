@@ -5,42 +5,80 @@ use criterion::{black_box, criterion_group, criterion_main, Criterion};
 
 use lexer::{Lexer, Lexicon, LexiconBuilder, Next};
 
-fn c_lexicon() -> Lexicon {
+/// The kinds of token in the C lexicon.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum C {
+    Comment,
+    Directive,
+    Include,
+    Ident,
+    Number,
+    Operator,
+    String,
+    Char,
+    LParen,
+    RParen,
+    Semi,
+    LBrace,
+    RBrace,
+    Comma,
+    LBracket,
+    RBracket,
+    Backslash,
+    Question,
+    Colon,
+    Dot,
+    If,
+    Else,
+    Const,
+    Return,
+    For,
+    Struct,
+    Switch,
+    Case,
+    While,
+    Do,
+    Break,
+    Static,
+    Extern,
+}
+
+fn c_lexicon() -> Lexicon<C> {
     LexiconBuilder::new()
         .ignore_chars(" \t\r\n")
-        .pattern(0, "/\\*([^*]|\\*+[^/])*\\*+/")
-        .pattern(1, "#[ \t]*[a-zA-Z]+")
-        .pattern(2, "<[a-zA-Z0-9_./]+>")
-        .pattern(3, "[a-zA-Z_][a-zA-Z0-9_]*")
-        .pattern(4, "[0-9]+")
-        .pattern(5, "[+\\-*/!%\\^|&<>=~]+")
-        .pattern(6, r#""(\\"|[^"]*)""#)
-        .pattern(7, "'[^']+'")
-        .literal(100, "(")
-        .literal(101, ")")
-        .literal(102, ";")
-        .literal(103, "{")
-        .literal(104, "}")
-        .literal(105, ",")
-        .literal(106, "[")
-        .literal(107, "]")
-        .literal(108, "\\")
-        .literal(109, "?")
-        .literal(110, ":")
-        .literal(111, ".")
-        .literal(200, "if")
-        .literal(201, "else")
-        .literal(202, "const")
-        .literal(203, "return")
-        .literal(204, "for")
-        .literal(205, "struct")
-        .literal(205, "switch")
-        .literal(206, "case")
-        .literal(207, "while")
-        .literal(208, "do")
-        .literal(208, "break")
-        .literal(209, "static")
-        .literal(210, "extern")
+        .pattern(C::Comment, "/\\*([^*]|\\*+[^/])*\\*+/")
+        .pattern(C::Directive, "^#[ \t]*[a-zA-Z]+")
+        .pattern(C::Include, "<[a-zA-Z0-9_./]+>")
+        .pattern(C::Ident, "[a-zA-Z_][a-zA-Z0-9_]*")
+        .pattern(C::Number, "[0-9]+")
+        .pattern(C::Operator, "[+\\-*/!%\\^|&<>=~]+")
+        .pattern(C::String, r#""(\\"|[^"]*)""#)
+        .pattern(C::Char, "'[^']+'")
+        .literal(C::LParen, "(")
+        .literal(C::RParen, ")")
+        .literal(C::Semi, ";")
+        .literal(C::LBrace, "{")
+        .literal(C::RBrace, "}")
+        .literal(C::Comma, ",")
+        .literal(C::LBracket, "[")
+        .literal(C::RBracket, "]")
+        .literal(C::Backslash, "\\")
+        .literal(C::Question, "?")
+        .literal(C::Colon, ":")
+        .literal(C::Dot, ".")
+        .literal(C::If, "if")
+        .literal(C::Else, "else")
+        .literal(C::Const, "const")
+        .literal(C::Return, "return")
+        .literal(C::For, "for")
+        .literal(C::Struct, "struct")
+        .literal(C::Switch, "switch")
+        .literal(C::Case, "case")
+        .literal(C::While, "while")
+        .literal(C::Do, "do")
+        .literal(C::Break, "break")
+        .literal(C::Static, "static")
+        .literal(C::Extern, "extern")
         .build()
         .unwrap()
 }
@@ -64,43 +102,7 @@ fn bench_sqlite3(c: &mut Criterion) {
     let mut contents = String::new();
     file.read_to_string(&mut contents).unwrap();
 
-    let lexicon = LexiconBuilder::new()
-        .ignore_chars(" \t\r\n")
-        .pattern(0, "/\\*([^*]|\\*+[^/])*\\*+/")
-        .pattern(1, "#[ \t]*[a-zA-Z]+")
-        .pattern(2, "<[a-zA-Z0-9_./]+>")
-        .pattern(3, "[a-zA-Z_][a-zA-Z0-9_]*")
-        .pattern(4, "[0-9]+")
-        .pattern(5, "[+\\-*/!%\\^|&<>=~]+")
-        .pattern(6, r#""(\\"|[^"]*)""#)
-        .pattern(7, "'[^']+'")
-        .literal(100, "(")
-        .literal(101, ")")
-        .literal(102, ";")
-        .literal(103, "{")
-        .literal(104, "}")
-        .literal(105, ",")
-        .literal(106, "[")
-        .literal(107, "]")
-        .literal(108, "\\")
-        .literal(109, "?")
-        .literal(110, ":")
-        .literal(111, ".")
-        .literal(200, "if")
-        .literal(201, "else")
-        .literal(202, "const")
-        .literal(203, "return")
-        .literal(204, "for")
-        .literal(205, "struct")
-        .literal(205, "switch")
-        .literal(206, "case")
-        .literal(207, "while")
-        .literal(208, "do")
-        .literal(208, "break")
-        .literal(209, "static")
-        .literal(210, "extern")
-        .build()
-        .unwrap();
+    let lexicon = c_lexicon();
 
     let mut lexer = Lexer::new(&lexicon, &contents);
     let mut count = 0;
@@ -110,9 +112,9 @@ fn bench_sqlite3(c: &mut Criterion) {
             lexer.reset();
             count = 0;
 
-            while let Some(t) = lexer.next() {
+            for t in lexer.by_ref() {
                 match t {
-                    Next::Token(_, _, _) => count += 1,
+                    Next::Token(_) => count += 1,
                     Next::Error(_, _) => {}
                 }
             }
@@ -134,9 +136,9 @@ fn bench_gzip(c: &mut Criterion) {
             lexer.reset();
             count = 0;
 
-            while let Some(t) = lexer.next() {
+            for t in lexer.by_ref() {
                 match t {
-                    Next::Token(_, _, _) => count += 1,
+                    Next::Token(_) => count += 1,
                     Next::Error(_, _) => {}
                 };
             }
@@ -166,9 +168,9 @@ fn bench_kjv(c: &mut Criterion) {
             lexer.reset();
             count = 0;
 
-            while let Some(t) = lexer.next() {
+            for t in lexer.by_ref() {
                 match t {
-                    Next::Token(_, _, _) => count += 1,
+                    Next::Token(_) => count += 1,
                     Next::Error(_, _) => {}
                 };
             }
@@ -0,0 +1,15 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+#[path = "src/lib.rs"]
+mod rules;
+
+fn main() {
+    let source =
+        lexer::codegen::generate(rules::builder(), "Kind", |k| format!("Kind::{}", k)).unwrap();
+    let out_dir = env::var("OUT_DIR").unwrap();
+    fs::write(Path::new(&out_dir).join("lexer.rs"), source).unwrap();
+
+    println!("cargo:rerun-if-changed=src/lib.rs");
+}
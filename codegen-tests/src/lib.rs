@@ -0,0 +1,17 @@
+//! The rules of the lexer that `build.rs` generates, which the tests lex
+//! with both the generated tables and a `Lexer`.
+
+use lexer::LexiconBuilder;
+
+/// Rules covering non-ASCII classes, anchors, word boundaries, skip rules and
+/// ignored chars, with kinds named after the variants of `Kind`.
+pub fn builder() -> LexiconBuilder<&'static str> {
+    LexiconBuilder::new()
+        .ignore_chars(" \n")
+        .skip(r"//[^\n]*")
+        .pattern("Ident", r"[a-zA-Zé]+")
+        .pattern("Number", r"[0-9]+$")
+        .pattern("Word", r"\bx[0-9]+\b")
+        .literal("If", "if")
+        .literal("Arrow", "→")
+}
@@ -0,0 +1,44 @@
+use lexer::{Error, Lexer, Next, Span};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Ident,
+    Number,
+    Word,
+    If,
+    Arrow,
+}
+
+include!(concat!(env!("OUT_DIR"), "/lexer.rs"));
+
+/// Returns the name of the kind of each token, along with its text and span.
+fn tokens<K>(
+    lexer: impl Iterator<Item = Next<'static, K>>,
+    name: impl Fn(K) -> String,
+) -> Vec<(Option<String>, &'static str, Span)> {
+    lexer
+        .map(|next| match next {
+            Next::Token(t) => (Some(name(t.kind)), t.text, t.span),
+            Next::Error(Error::UnexpectedChar(text), span) => (None, text, span),
+        })
+        .collect()
+}
+
+#[test]
+fn matches_lexer() {
+    let lexicon = codegen_tests::builder().build().unwrap();
+
+    let inputs = [
+        "if iffy 12 // c\n3 4\n",
+        "café → if",
+        "x1 x12y ax1 x3",
+        "1 x - 2",
+        "",
+        "  ",
+    ];
+    for input in inputs.iter() {
+        let expected = tokens(Lexer::new(&lexicon, input), String::from);
+        let generated = tokens(lex(input), |kind| format!("{:?}", kind));
+        assert_eq!(generated, expected, "{:?}", input);
+    }
+}
@@ -0,0 +1,138 @@
+//! `#[derive(Lexicon)]` for token kinds, implementing `lexer::TokenKind` from
+//! attributes on an enum and its variants:
+//!
+//! ```
+//! use lexer::TokenKind;
+//! use lexer_derive::Lexicon;
+//!
+//! #[derive(Clone, Copy, Debug, PartialEq, Lexicon)]
+//! #[skip(" \t\n")]
+//! #[skip(regex = "//[^\n]*")]
+//! enum Kind {
+//!     #[token("if")]
+//!     If,
+//!     #[regex("[a-z]+")]
+//!     Ident,
+//! }
+//!
+//! let lexicon = Kind::lexicon();
+//! ```
+//!
+//! `#[token]` adds a literal and `#[regex]` a pattern for the variant, and a
+//! variant may have several of either. `#[skip]` on the enum gives chars to
+//! ignore, or with `regex = "..."` a pattern whose matches are skipped, such
+//! as comments. Each rule is built while the derive expands, so an invalid
+//! pattern is a compile error pointing at it.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, LitStr, Result};
+
+use lexer::{LexiconBuilder, LexiconBuilderError};
+
+#[proc_macro_derive(Lexicon, attributes(token, regex, skip))]
+pub fn derive_lexicon(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    expand(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: &DeriveInput) -> Result<TokenStream2> {
+    let data = match &input.data {
+        Data::Enum(data) => data,
+        _ => return Err(Error::new_spanned(input, "only enums can derive `Lexicon`")),
+    };
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "token kinds cannot be generic",
+        ));
+    }
+
+    let mut calls = vec![];
+    for attr in &input.attrs {
+        if !attr.path().is_ident("skip") {
+            continue;
+        }
+
+        if let Ok(chars) = attr.parse_args::<LitStr>() {
+            calls.push(quote!(.ignore_chars(#chars)));
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if !meta.path.is_ident("regex") {
+                return Err(meta.error("expected chars to ignore or `regex = \"...\"`"));
+            }
+
+            let pattern = meta.value()?.parse::<LitStr>()?;
+            check(LexiconBuilder::new().skip(&pattern.value()), &pattern)?;
+            calls.push(quote!(.skip(#pattern)));
+            Ok(())
+        })?;
+    }
+
+    for variant in &data.variants {
+        let name = &variant.ident;
+
+        for attr in &variant.attrs {
+            let path = attr.path();
+            if path.is_ident("skip") {
+                return Err(Error::new_spanned(
+                    attr,
+                    "`#[skip]` goes on the enum rather than a variant",
+                ));
+            }
+            if !path.is_ident("token") && !path.is_ident("regex") {
+                continue;
+            }
+
+            if !matches!(variant.fields, Fields::Unit) {
+                return Err(Error::new_spanned(
+                    &variant.fields,
+                    "variants with rules cannot have fields",
+                ));
+            }
+
+            let source = attr.parse_args::<LitStr>()?;
+            if path.is_ident("token") {
+                check(LexiconBuilder::new().literal((), &source.value()), &source)?;
+                calls.push(quote!(.literal(Self::#name, #source)));
+            } else {
+                check(LexiconBuilder::new().pattern((), &source.value()), &source)?;
+                calls.push(quote!(.pattern(Self::#name, #source)));
+            }
+        }
+    }
+
+    let ident = &input.ident;
+    Ok(quote! {
+        impl ::lexer::TokenKind for #ident {
+            fn builder() -> ::lexer::LexiconBuilder<Self> {
+                ::lexer::LexiconBuilder::new() #(#calls)*
+            }
+        }
+    })
+}
+
+/// Builds a lexicon of a single rule, reporting why it is invalid at the span
+/// of its source. It simulates the rule's NFA, so that no DFA is built.
+fn check(builder: LexiconBuilder<()>, source: &LitStr) -> Result<()> {
+    let message = match builder.pike_vm().build() {
+        Ok(_) => return Ok(()),
+        Err(LexiconBuilderError::InvalidRegex(e)) => e.to_string(),
+        Err(LexiconBuilderError::EmptyLiteral(())) => "empty literal".into(),
+        Err(LexiconBuilderError::EmptyTrailingContextHead(())) => {
+            "pattern before the trailing context matches the empty string".into()
+        }
+        Err(LexiconBuilderError::EmptyShortestPattern(())) => {
+            "shortest-match pattern matches the empty string".into()
+        }
+    };
+
+    Err(Error::new(source.span(), message))
+}
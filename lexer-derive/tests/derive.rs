@@ -0,0 +1,58 @@
+use lexer::{Lexer, Next, TokenKind};
+use lexer_derive::Lexicon;
+
+#[derive(Clone, Copy, Debug, PartialEq, Lexicon)]
+#[skip(" \t\n")]
+#[skip(regex = "#[^\n]*")]
+enum Kind {
+    #[token("if")]
+    #[token("elif")]
+    Keyword,
+    #[regex("[a-z]+")]
+    Ident,
+    #[regex("[0-9]+")]
+    Number,
+    #[token("=")]
+    Equals,
+}
+
+#[test]
+fn derive() {
+    let lexicon = Kind::lexicon();
+    let kinds = Lexer::new(&lexicon, "if x = 1 # one\nelif iffy")
+        .map(|next| match next {
+            Next::Token(token) => Ok((token.kind, token.text)),
+            Next::Error(error, _) => Err(error),
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+
+    assert_eq!(
+        kinds,
+        [
+            (Kind::Keyword, "if"),
+            (Kind::Ident, "x"),
+            (Kind::Equals, "="),
+            (Kind::Number, "1"),
+            (Kind::Keyword, "elif"),
+            (Kind::Ident, "iffy"),
+        ]
+    );
+}
+
+#[test]
+fn builder() {
+    let lexicon = Kind::builder().pike_vm().build().unwrap();
+    let mut lexer = Lexer::new(&lexicon, "a");
+
+    match lexer.next() {
+        Some(Next::Token(token)) => assert_eq!(token.kind, Kind::Ident),
+        next => panic!("{:?}", next),
+    }
+}
+
+#[test]
+fn compile_errors() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+}
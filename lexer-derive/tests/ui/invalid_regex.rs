@@ -0,0 +1,9 @@
+use lexer_derive::Lexicon;
+
+#[derive(Clone, Copy, Debug, Lexicon)]
+enum Kind {
+    #[regex("[a-z")]
+    Ident,
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use lexer_derive::Lexicon;
+
+#[derive(Clone, Copy, Debug, Lexicon)]
+#[skip(regex = "(a")]
+enum Kind {
+    #[token("a")]
+    A,
+}
+
+fn main() {}
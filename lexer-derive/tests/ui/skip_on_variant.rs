@@ -0,0 +1,10 @@
+use lexer_derive::Lexicon;
+
+#[derive(Clone, Copy, Debug, Lexicon)]
+enum Kind {
+    #[skip(" ")]
+    #[token("a")]
+    A,
+}
+
+fn main() {}
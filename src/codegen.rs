@@ -0,0 +1,329 @@
+//! Generates Rust source for a lexer from a `LexiconBuilder`, typically from a
+//! build script, so that no patterns are compiled at runtime:
+//!
+//! ```ignore
+//! // build.rs, where kinds are the names of the variants of `Kind`
+//! let builder = LexiconBuilder::new()
+//!     .ignore_chars(" \t\n")
+//!     .pattern("Ident", "[a-z]+")
+//!     .literal("If", "if");
+//! let source = lexer::codegen::generate(builder, "Kind", |k| format!("Kind::{}", k))?;
+//! std::fs::write(Path::new(&env::var("OUT_DIR")?).join("lexer.rs"), source)?;
+//!
+//! // src/main.rs
+//! include!(concat!(env!("OUT_DIR"), "/lexer.rs"));
+//!
+//! for next in lex("if x") { ... }
+//! ```
+//!
+//! The generated source holds the tables of the lexicon's DFA in a static
+//! named `LEXICON`, along with a `lex` function that returns a `StaticLexer`
+//! over them, both private to the module including them. It only depends on
+//! this crate, which can then be built without the `regex-syntax` feature, as
+//! long as Cargo's version 2 resolver keeps the features of the build
+//! dependency apart.
+
+use std::fmt::{self, Write};
+
+use crate::dfa::{DEAD, DEAD_ID, DFA};
+use crate::lexer::{Error as LexerError, Next, Position, Span, Token};
+use crate::lexicon::{Automaton, Error as LexiconError, Lexicon, LexiconBuilder};
+use crate::nfa::Context;
+
+/// The tables of a DFA and the rules it matches, as written by `generate`.
+/// State and rule ids that do not exist are `NONE`.
+pub struct Tables<K: 'static> {
+    pub ascii: [u32; 128],
+    pub ranges: &'static [(char, char, u32)],
+    pub classes: usize,
+    pub contexts: &'static [u32],
+    pub starts: &'static [u32],
+    pub transitions: &'static [u32],
+    pub accepts: &'static [u32],
+    /// The kind of each rule, or `None` for skip rules.
+    pub kinds: &'static [Option<K>],
+    pub ignore_chars: &'static [char],
+}
+
+pub const NONE: u32 = u32::MAX;
+
+/// Lexes `&str` input with generated `Tables`, like a `Lexer` but without
+/// allocating.
+pub struct StaticLexer<'input, K: 'static> {
+    tables: &'static Tables<K>,
+    input: &'input str,
+    pos: Position,
+}
+
+#[derive(Debug)]
+pub enum Error<K = usize> {
+    Lexicon(LexiconError<K>),
+    /// The lexicon uses a feature that generated lexers do not support.
+    Unsupported(&'static str),
+}
+
+impl<K> Tables<K> {
+    fn class_of(&self, c: char) -> usize {
+        if (c as u32) < 128 {
+            return self.ascii[c as usize] as usize;
+        }
+
+        match self.ranges.binary_search_by(|(lo, hi, _)| {
+            if *hi < c {
+                std::cmp::Ordering::Less
+            } else if *lo > c {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        }) {
+            Ok(i) => self.ranges[i].2 as usize,
+            Err(_) => 0,
+        }
+    }
+
+    fn accept(&self, state: u32, next: usize) -> Option<usize> {
+        match self.accepts[state as usize * self.starts.len() + next] {
+            NONE => None,
+            rule => Some(rule as usize),
+        }
+    }
+
+    /// Returns the rule and length in bytes of the longest non-empty match at
+    /// the start of `input`, which follows the char `prev`, exactly as
+    /// `DFA::longest_match` does.
+    fn longest_match(&self, input: &str, prev: Option<char>) -> Option<(usize, usize)> {
+        let mut state = match prev {
+            Some(c) => self.starts[self.contexts[self.class_of(c)] as usize],
+            None => self.starts[Context::Edge as usize],
+        };
+        let mut best = None;
+        let mut len = 0;
+
+        for (start, c) in input.char_indices() {
+            let class = self.class_of(c);
+            if len > 0 {
+                if let Some(rule) = self.accept(state, self.contexts[class] as usize) {
+                    best = Some((rule, len));
+                }
+            }
+
+            state = self.transitions[state as usize * self.classes + class];
+            if state == NONE {
+                return best;
+            }
+            len = start + c.len_utf8();
+        }
+
+        if len > 0 {
+            if let Some(rule) = self.accept(state, Context::Edge as usize) {
+                best = Some((rule, len));
+            }
+        }
+
+        best
+    }
+}
+
+impl<'input, K: Copy> StaticLexer<'input, K> {
+    pub fn new(tables: &'static Tables<K>, input: &'input str) -> Self {
+        StaticLexer {
+            tables,
+            input,
+            pos: Position::new(0, 1, 1),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.pos = Position::new(0, 1, 1);
+    }
+}
+
+impl<'input, K: Copy> Iterator for StaticLexer<'input, K> {
+    type Item = Next<'input, K>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let rest = &self.input[self.pos.offset()..];
+            let c = rest.chars().next()?;
+            if self.tables.ignore_chars.contains(&c) {
+                self.pos.advance(Some(c), c.len_utf8());
+                continue;
+            }
+
+            let start = self.pos;
+            let prev = self.input[..start.offset()].chars().next_back();
+
+            let kind = match self.tables.longest_match(rest, prev) {
+                Some((rule, len)) => {
+                    for c in rest[..len].chars() {
+                        self.pos.advance(Some(c), c.len_utf8());
+                    }
+                    match self.tables.kinds[rule] {
+                        Some(kind) => Some(kind),
+                        None => continue,
+                    }
+                }
+                None => {
+                    self.pos.advance(Some(c), c.len_utf8());
+                    None
+                }
+            };
+
+            let span = Span::new(start, self.pos);
+            let text = &self.input[span.range()];
+            return Some(match kind {
+                Some(kind) => Next::Token(Token { kind, text, span }),
+                None => Next::Error(LexerError::UnexpectedChar(text), span),
+            });
+        }
+    }
+}
+
+/// Returns Rust source declaring `LEXICON`, the `Tables` of the lexicon
+/// `builder` builds, and a `lex` function to lex with them. Kinds have the
+/// type named `kind_type`, and are written as the expressions `kind` returns.
+///
+/// Generated lexers match with a DFA, whichever engine `builder` chose, and
+/// do not support modes, trailing context or captures.
+pub fn generate<K: Copy>(
+    builder: LexiconBuilder<K>,
+    kind_type: &str,
+    kind: impl Fn(K) -> String,
+) -> Result<String, Error<K>> {
+    let lexicon = builder.dfa().build().map_err(Error::Lexicon)?;
+    let dfa = tables(&lexicon)?;
+
+    let mut out = String::new();
+    write_tables(&mut out, &lexicon, dfa, kind_type, kind).unwrap();
+
+    Ok(out)
+}
+
+/// Returns the DFA of a lexicon, if `generate` supports it.
+fn tables<K>(lexicon: &Lexicon<K>) -> Result<&DFA, Error<K>> {
+    if lexicon.modes.len() > 1 || lexicon.rules.iter().any(|r| r.action.is_some()) {
+        return Err(Error::Unsupported("modes"));
+    }
+    if lexicon.rules.iter().any(|r| r.trailing_context.is_some()) {
+        return Err(Error::Unsupported("trailing context"));
+    }
+
+    match &lexicon.modes[0].automaton {
+        Automaton::Dfa(dfa) => Ok(dfa.as_ref()),
+        _ => unreachable!(),
+    }
+}
+
+fn write_tables<K: Copy>(
+    out: &mut String,
+    lexicon: &Lexicon<K>,
+    dfa: &DFA,
+    kind_type: &str,
+    kind: impl Fn(K) -> String,
+) -> fmt::Result {
+    let id = |id: usize| {
+        if id == DEAD {
+            "NONE".to_string()
+        } else {
+            id.to_string()
+        }
+    };
+    let accept = |rule: &Option<usize>| rule.map_or("NONE".to_string(), |r| r.to_string());
+    let kinds = lexicon.modes[0]
+        .rules
+        .iter()
+        .map(|&i| match lexicon.rules[i].kind {
+            Some(k) => format!("Some({})", kind(k)),
+            None => "None".to_string(),
+        });
+    let mut ignore_chars = lexicon.ignore_chars.iter().copied().collect::<Vec<_>>();
+    ignore_chars.sort_unstable();
+
+    writeln!(out, "// Generated by `lexer::codegen`, do not edit.")?;
+    writeln!(out)?;
+    writeln!(out, "use ::lexer::codegen::{{StaticLexer, Tables, NONE}};")?;
+    writeln!(out)?;
+    writeln!(out, "static LEXICON: Tables<{}> = Tables {{", kind_type)?;
+    write_list(out, "ascii", dfa.alphabet.ascii.iter().map(u32::to_string))?;
+    write_list(
+        out,
+        "ranges",
+        dfa.alphabet
+            .ranges
+            .iter()
+            .map(|(lo, hi, class)| format!("({:?}, {:?}, {})", lo, hi, class)),
+    )?;
+    writeln!(out, "    classes: {},", dfa.alphabet.len())?;
+    write_list(out, "contexts", dfa.contexts.iter().map(usize::to_string))?;
+    write_list(out, "starts", dfa.starts.iter().map(|&s| id(s)))?;
+    write_list(
+        out,
+        "transitions",
+        dfa.transitions.iter().map(|&s| match s {
+            DEAD_ID => "NONE".to_string(),
+            s => s.to_string(),
+        }),
+    )?;
+    write_list(out, "accepts", dfa.accepts.iter().map(accept))?;
+    write_list(out, "kinds", kinds)?;
+    write_list(
+        out,
+        "ignore_chars",
+        ignore_chars.iter().map(|c| format!("{:?}", c)),
+    )?;
+    writeln!(out, "}};")?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "fn lex(input: &str) -> StaticLexer<'_, {}> {{",
+        kind_type
+    )?;
+    writeln!(out, "    StaticLexer::new(&LEXICON, input)")?;
+    writeln!(out, "}}")
+}
+
+/// Writes a field holding a slice of `items`, or an array for `ascii`.
+fn write_list(out: &mut String, field: &str, items: impl Iterator<Item = String>) -> fmt::Result {
+    let prefix = if field == "ascii" { "" } else { "&" };
+    write!(out, "    {}: {}[", field, prefix)?;
+    for (i, item) in items.enumerate() {
+        if i % 16 == 0 {
+            write!(out, "\n        ")?;
+        } else {
+            write!(out, " ")?;
+        }
+        write!(out, "{},", item)?;
+    }
+    writeln!(out, "\n    ],")
+}
+
+#[cfg(all(test, feature = "regex-syntax"))]
+mod tests {
+    use super::generate;
+    use crate::dfa::DEAD_ID;
+    use crate::LexiconBuilder;
+
+    fn builder() -> LexiconBuilder {
+        LexiconBuilder::new()
+            .ignore_chars(" \n")
+            .skip(r"//[^\n]*")
+            .pattern(0, r"[a-zA-Zé]+")
+            .pattern(1, r"[0-9]+$")
+            .literal(2, "if")
+    }
+
+    #[test]
+    fn source() {
+        let source = generate(builder(), "u8", |k| format!("{}u8", k)).unwrap();
+
+        assert!(source.contains("static LEXICON: Tables<u8> = Tables {"));
+        assert!(source.contains("None, Some(0u8), Some(1u8), Some(2u8),"));
+        assert!(source.contains("fn lex(input: &str) -> StaticLexer<'_, u8> {"));
+        assert!(source.contains("NONE,"));
+        assert!(!source.contains(&DEAD_ID.to_string()));
+
+        let modes = builder().modes(&[1]).literal(3, "x");
+        assert!(generate(modes, "usize", |k| k.to_string()).is_err());
+    }
+}
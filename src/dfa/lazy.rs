@@ -0,0 +1,324 @@
+use std::mem;
+use std::sync::Arc;
+
+use hashbrown::HashMap;
+
+use super::{context_indexes, StateID, DEAD};
+use crate::nfa::{Context, PikeVM, Prefixes, States, NFA};
+
+/// The cache is only abandoned after it has been cleared this many times.
+const MIN_CLEARS: usize = 3;
+
+/// A cache that is cleared before it has processed this many chars per
+/// cached state is considered to be thrashing.
+const MIN_CHARS_PER_STATE: usize = 10;
+
+const UNKNOWN: StateID = usize::MAX - 1;
+
+/// A DFA whose states are built from the NFA as input is consumed.
+///
+/// Built states and transitions are cached until the cache exceeds its size
+/// limit, at which point it is cleared and rebuilt. When the cache keeps
+/// getting cleared without making much progress, matching falls back to
+/// simulating the NFA directly with a `PikeVM`.
+pub struct LazyDFA {
+    nfa: NFA,
+    precedences: Vec<u8>,
+    contexts: Vec<usize>,
+    rows: usize,
+    keep_prev: bool,
+    cache_size: usize,
+    cache: Cache,
+    scratch: States,
+    resolved: States,
+    vm: PikeVM,
+    clears: usize,
+    chars_since_clear: usize,
+    fallback: bool,
+}
+
+struct Cache {
+    states: Vec<CachedState>,
+    ids: HashMap<(Vec<StateID>, Context), StateID>,
+    starts: Vec<Option<StateID>>,
+    memory: usize,
+}
+
+struct CachedState {
+    nfa_states: Vec<StateID>,
+    prev: Context,
+    accepts: Vec<Option<usize>>,
+    transitions: Vec<StateID>,
+}
+
+/// Returned when the cache is thrashing and the NFA should be used instead.
+struct GaveUp;
+
+impl LazyDFA {
+    pub fn new(
+        nfa: NFA,
+        precedences: Vec<u8>,
+        prefixes: Arc<Prefixes>,
+        cache_size: usize,
+    ) -> LazyDFA {
+        let rows = if nfa.contexts.is_some() {
+            Context::ALL.len()
+        } else {
+            1
+        };
+        let scratch = nfa.states();
+        let resolved = nfa.states();
+        let vm = PikeVM::new(nfa.clone(), precedences.clone(), prefixes);
+
+        LazyDFA {
+            contexts: context_indexes(&nfa),
+            rows,
+            keep_prev: nfa.has_word_boundaries(),
+            nfa,
+            precedences,
+            cache_size,
+            cache: Cache::new(rows),
+            scratch,
+            resolved,
+            vm,
+            clears: 0,
+            chars_since_clear: 0,
+            fallback: false,
+        }
+    }
+
+    /// Returns the rule and length in bytes of the longest non-empty match at
+    /// the start of the input yielding `symbols`, which follows the char
+    /// `prev`.
+    pub fn longest_match<I>(&mut self, symbols: I, prev: Option<char>) -> Option<(usize, usize)>
+    where
+        I: Iterator<Item = (usize, char)> + Clone,
+    {
+        if !self.fallback {
+            match self.dfa_longest_match(symbols.clone(), prev) {
+                Ok(m) => return m,
+                Err(GaveUp) => self.fallback = true,
+            }
+        }
+
+        self.vm.longest_match(symbols, prev)
+    }
+
+    fn dfa_longest_match(
+        &mut self,
+        symbols: impl Iterator<Item = (usize, char)>,
+        prev: Option<char>,
+    ) -> Result<Option<(usize, usize)>, GaveUp> {
+        let mut state = self.start(prev)?;
+        let mut best = None;
+        let mut len = 0;
+
+        for (end, c) in symbols {
+            self.chars_since_clear += 1;
+
+            let class = self.nfa.alphabet.class_of(c);
+            if len > 0 {
+                if let Some(rule) = self.cache.states[state].accepts[self.contexts[class]] {
+                    best = Some((rule, len));
+                }
+            }
+
+            state = match self.next_state(state, class)? {
+                Some(next) => next,
+                None => return Ok(best),
+            };
+            len = end;
+        }
+
+        if len > 0 {
+            if let Some(rule) = self.cache.states[state].accepts[Context::Edge as usize] {
+                best = Some((rule, len));
+            }
+        }
+
+        Ok(best)
+    }
+
+    fn start(&mut self, prev: Option<char>) -> Result<StateID, GaveUp> {
+        let row = match prev {
+            Some(c) => self.contexts[self.nfa.alphabet.class_of(c)],
+            None => Context::Edge as usize,
+        };
+        if let Some(start) = self.cache.starts[row] {
+            return Ok(start);
+        }
+
+        let prev = Context::ALL[row];
+        self.nfa.initialize_states(&mut self.scratch, prev);
+        let prev = if self.keep_prev { prev } else { Context::Other };
+        let start = self.add_state(self.scratch.ones().collect(), prev)?;
+        self.cache.starts[row] = Some(start);
+
+        Ok(start)
+    }
+
+    fn next_state(&mut self, from: StateID, class: usize) -> Result<Option<StateID>, GaveUp> {
+        match self.cache.states[from].transitions[class] {
+            DEAD => return Ok(None),
+            UNKNOWN => {}
+            to => return Ok(Some(to)),
+        }
+
+        let nfa_states = mem::take(&mut self.cache.states[from].nfa_states);
+        if self.nfa.contexts.is_some() {
+            let prev = self.cache.states[from].prev;
+            let next = Context::ALL[self.contexts[class]];
+            self.nfa
+                .resolve(nfa_states.iter().copied(), prev, next, &mut self.resolved);
+            self.nfa
+                .step_class(self.resolved.ones(), class, &mut self.scratch);
+        } else {
+            self.nfa
+                .step_class(nfa_states.iter().copied(), class, &mut self.scratch);
+        }
+        self.cache.states[from].nfa_states = nfa_states;
+
+        let next = self.scratch.ones().collect::<Vec<_>>();
+        let prev = if self.keep_prev {
+            self.nfa.context_of_class(class)
+        } else {
+            Context::Other
+        };
+        let clears = self.clears;
+        let to = if next.is_empty() {
+            DEAD
+        } else {
+            self.add_state(next, prev)?
+        };
+
+        // A cleared cache no longer contains the state being transitioned from
+        if self.clears == clears {
+            self.cache.states[from].transitions[class] = to;
+        }
+
+        Ok(if to == DEAD { None } else { Some(to) })
+    }
+
+    fn add_state(&mut self, nfa_states: Vec<StateID>, prev: Context) -> Result<StateID, GaveUp> {
+        let key = (nfa_states, prev);
+        if let Some(id) = self.cache.ids.get(&key) {
+            return Ok(*id);
+        }
+        let (nfa_states, prev) = key;
+
+        let classes = self.nfa.alphabet.len();
+        let memory = mem::size_of::<CachedState>()
+            + (2 * nfa_states.len() + classes) * mem::size_of::<StateID>()
+            + self.rows * mem::size_of::<Option<usize>>();
+        if !self.cache.states.is_empty() && self.cache.memory + memory > self.cache_size {
+            self.clear()?;
+        }
+
+        let accepts = if self.nfa.contexts.is_some() {
+            let mut accepts = vec![];
+            for &next in Context::ALL.iter() {
+                self.nfa
+                    .resolve(nfa_states.iter().copied(), prev, next, &mut self.resolved);
+                accepts.push(
+                    self.nfa
+                        .accepting_rule(self.resolved.ones(), &self.precedences),
+                );
+            }
+            accepts
+        } else {
+            vec![self
+                .nfa
+                .accepting_rule(nfa_states.iter().copied(), &self.precedences)]
+        };
+
+        let id = self.cache.states.len();
+        self.cache.ids.insert((nfa_states.clone(), prev), id);
+        self.cache.states.push(CachedState {
+            nfa_states,
+            prev,
+            accepts,
+            transitions: vec![UNKNOWN; classes],
+        });
+        self.cache.memory += memory;
+
+        Ok(id)
+    }
+
+    fn clear(&mut self) -> Result<(), GaveUp> {
+        let thrashing = self.clears >= MIN_CLEARS
+            && self.chars_since_clear < MIN_CHARS_PER_STATE * self.cache.states.len();
+
+        self.cache = Cache::new(self.rows);
+        self.clears += 1;
+        self.chars_since_clear = 0;
+
+        if thrashing {
+            return Err(GaveUp);
+        }
+
+        Ok(())
+    }
+}
+
+impl Cache {
+    fn new(rows: usize) -> Cache {
+        Cache {
+            states: vec![],
+            ids: HashMap::new(),
+            starts: vec![None; rows],
+            memory: 0,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "regex-syntax"))]
+mod tests {
+    use std::sync::Arc;
+
+    use super::LazyDFA;
+    use crate::dfa::DFA;
+    use crate::nfa::{symbols, Prefixes, NFA};
+
+    fn nfa(patterns: &[&str]) -> (NFA, Arc<Prefixes>) {
+        let nfas = patterns
+            .iter()
+            .map(|p| NFA::from_regex(p).unwrap())
+            .collect::<Vec<_>>();
+        let ranges = nfas.iter().map(|n| n.starting_ranges()).collect::<Vec<_>>();
+
+        (NFA::union(&nfas), Arc::new(Prefixes::new(&ranges)))
+    }
+
+    #[test]
+    fn matches_eager_dfa() {
+        let (nfa, prefixes) = nfa(&[r"\w+", r#""[^"]*""#, "[0-9]+", r"\s+"]);
+        let dfa = DFA::from_nfa(&nfa, &[0, 0, 1, 0]);
+        let mut lazy = LazyDFA::new(nfa, vec![0, 0, 1, 0], prefixes, 1 << 20);
+
+        for input in &["hello", "123", "\"ünïcode\" x", "  \n", "ωμέγα2", "!", ""] {
+            assert_eq!(
+                lazy.longest_match(symbols(input), None),
+                dfa.longest_match(symbols(input), None)
+            );
+        }
+        assert!(!lazy.fallback);
+    }
+
+    #[test]
+    fn falls_back_when_thrashing() {
+        let (nfa, prefixes) = nfa(&["(a|b)*abbb", "[ab]"]);
+        let dfa = DFA::from_nfa(&nfa, &[0, 0]);
+        let mut lazy = LazyDFA::new(nfa, vec![0, 0], prefixes, 0);
+
+        let inputs = ["abbb", "aababbb", "bbbb", "ab", "a"];
+        for _ in 0..10 {
+            for input in inputs.iter() {
+                assert_eq!(
+                    lazy.longest_match(symbols(input), None),
+                    dfa.longest_match(symbols(input), None)
+                );
+            }
+        }
+        assert!(lazy.fallback);
+    }
+}
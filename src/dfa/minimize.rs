@@ -0,0 +1,206 @@
+use hashbrown::HashMap;
+
+use super::{StateID, DEAD, DEAD_ID, DFA};
+
+impl DFA {
+    /// Merges equivalent states using Hopcroft's partition refinement.
+    ///
+    /// States start out partitioned by the rules they accept, so states that
+    /// accept different rules are never merged.
+    pub fn minimize(&self) -> DFA {
+        let k = self.alphabet.len();
+        let rows = self.starts.len();
+        let n = self.state_count() + 1;
+        let dead = n - 1;
+        let target = |s: StateID, class: usize| match s {
+            s if s == dead => dead,
+            s => match self.transition(s, class) {
+                DEAD => dead,
+                to => to,
+            },
+        };
+
+        let mut inverse = vec![vec![]; n * k];
+        for s in 0..n {
+            for class in 0..k {
+                inverse[target(s, class) * k + class].push(s);
+            }
+        }
+
+        let rejects = vec![None; rows];
+        let mut initial: HashMap<&[Option<usize>], Vec<StateID>> = HashMap::new();
+        for s in 0..n {
+            let accepts = match s {
+                s if s == dead => &rejects[..],
+                s => &self.accepts[s * rows..(s + 1) * rows],
+            };
+            initial.entry(accepts).or_default().push(s);
+        }
+
+        let mut blocks = initial.into_iter().map(|(_, b)| b).collect::<Vec<_>>();
+        blocks.sort();
+
+        let mut block_of = vec![0; n];
+        for (b, states) in blocks.iter().enumerate() {
+            for &s in states {
+                block_of[s] = b;
+            }
+        }
+
+        let mut pending = (0..blocks.len()).collect::<Vec<_>>();
+        let mut in_pending = vec![true; blocks.len()];
+        let mut marked = vec![false; n];
+
+        while let Some(splitter) = pending.pop() {
+            in_pending[splitter] = false;
+            let members = blocks[splitter].clone();
+
+            for class in 0..k {
+                let mut touched = vec![];
+                for &t in members.iter() {
+                    for &s in inverse[t * k + class].iter() {
+                        if !marked[s] {
+                            marked[s] = true;
+                            touched.push(s);
+                        }
+                    }
+                }
+
+                let mut affected = touched.iter().map(|&s| block_of[s]).collect::<Vec<_>>();
+                affected.sort_unstable();
+                affected.dedup();
+
+                for b in affected {
+                    let (inside, outside): (Vec<_>, Vec<_>) =
+                        blocks[b].iter().partition(|&&s| marked[s]);
+                    if outside.is_empty() {
+                        continue;
+                    }
+
+                    let (kept, split) = if inside.len() <= outside.len() {
+                        (outside, inside)
+                    } else {
+                        (inside, outside)
+                    };
+
+                    let new_block = blocks.len();
+                    for &s in split.iter() {
+                        block_of[s] = new_block;
+                    }
+                    blocks[b] = kept;
+                    blocks.push(split);
+
+                    in_pending.push(true);
+                    pending.push(new_block);
+                    if !in_pending[b] {
+                        in_pending[b] = true;
+                        pending.push(b);
+                    }
+                }
+
+                for s in touched {
+                    marked[s] = false;
+                }
+            }
+        }
+
+        self.merge(&block_of, dead)
+    }
+
+    /// Collapses each block of equivalent states into a single state. The
+    /// block containing `dead` is dropped, unless it also contains a start
+    /// state.
+    fn merge(&self, block_of: &[usize], dead: StateID) -> DFA {
+        let k = self.alphabet.len();
+        let rows = self.starts.len();
+        let mut ids = HashMap::new();
+        let mut representatives = vec![];
+        for (s, &block) in block_of.iter().enumerate().take(self.state_count()) {
+            let is_start = self.starts.contains(&s);
+            if (!is_start && block == block_of[dead]) || ids.contains_key(&block) {
+                continue;
+            }
+
+            ids.insert(block, representatives.len());
+            representatives.push(s);
+        }
+
+        let mut transitions = Vec::with_capacity(representatives.len() * k);
+        for &s in representatives.iter() {
+            for class in 0..k {
+                let to = match self.transition(s, class) {
+                    DEAD => DEAD_ID,
+                    to if block_of[to] == block_of[dead] => DEAD_ID,
+                    to => ids[&block_of[to]] as u32,
+                };
+                transitions.push(to);
+            }
+        }
+
+        DFA {
+            alphabet: self.alphabet.clone(),
+            contexts: self.contexts.clone(),
+            starts: self.starts.iter().map(|s| ids[&block_of[*s]]).collect(),
+            transitions: transitions.into(),
+            accepts: representatives
+                .iter()
+                .flat_map(|&s| self.accepts[s * rows..(s + 1) * rows].iter().copied())
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DFA;
+    use crate::nfa::{symbols, NFA};
+
+    fn dfa(nfas: &[NFA]) -> DFA {
+        let precedences = vec![0; nfas.len()];
+
+        DFA::from_nfa(&NFA::union(nfas), &precedences)
+    }
+
+    #[cfg(feature = "regex-syntax")]
+    #[test]
+    fn merges_equivalent_states() {
+        let dfa = dfa(&[NFA::from_regex("ab|cb").unwrap()]);
+        let minimized = dfa.minimize();
+
+        assert_eq!(dfa.state_count(), 4);
+        assert_eq!(minimized.state_count(), 3);
+        assert_eq!(minimized.longest_match(symbols("ab"), None), Some((0, 2)));
+        assert_eq!(minimized.longest_match(symbols("cb"), None), Some((0, 2)));
+        assert_eq!(minimized.longest_match(symbols("bb"), None), None);
+    }
+
+    #[test]
+    fn keeps_rules_distinct() {
+        let dfa = dfa(&[NFA::from_literal("ab"), NFA::from_literal("cb")]);
+        let minimized = dfa.minimize();
+
+        assert_eq!(minimized.state_count(), 5);
+        assert_eq!(minimized.longest_match(symbols("ab"), None), Some((0, 2)));
+        assert_eq!(minimized.longest_match(symbols("cb"), None), Some((1, 2)));
+    }
+
+    #[cfg(feature = "regex-syntax")]
+    #[test]
+    fn keywords() {
+        let dfa = dfa(&[
+            NFA::from_regex("[a-z]+").unwrap(),
+            NFA::from_literal("for"),
+            NFA::from_literal("if"),
+            NFA::from_regex("0x[0-9a-f]+|0X[0-9a-f]+").unwrap(),
+        ]);
+        let minimized = dfa.minimize();
+
+        assert!(minimized.state_count() < dfa.state_count());
+        for input in &["for", "fort", "if", "i", "iffy", "0x1f", "0Xff", "0x"] {
+            assert_eq!(
+                minimized.longest_match(symbols(input), None),
+                dfa.longest_match(symbols(input), None)
+            );
+        }
+    }
+}
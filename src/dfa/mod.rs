@@ -0,0 +1,307 @@
+mod lazy;
+mod minimize;
+
+pub use crate::dfa::lazy::LazyDFA;
+
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use hashbrown::HashMap;
+
+use crate::nfa::{Alphabet, Context, NFA};
+
+/// A DFA whose transitions are indexed by the classes of an `Alphabet`.
+///
+/// When the NFA has look states, whether a state accepts depends on the
+/// context after it, and which state to start in depends on the context before
+/// the input. Accepts and start states are then kept for every context.
+/// Otherwise, a single context stands in for all of them.
+#[derive(Clone, Debug)]
+pub struct DFA {
+    pub(crate) alphabet: Arc<Alphabet>,
+    pub(crate) contexts: Vec<usize>,
+    pub(crate) starts: Vec<StateID>,
+    /// The state each state moves to on each class, or `DEAD_ID`. Ids are
+    /// kept as `u32` so that a serialized table can be borrowed as it is.
+    pub(crate) transitions: Cow<'static, [u32]>,
+    pub(crate) accepts: Vec<Option<usize>>,
+}
+
+pub type StateID = usize;
+
+pub const DEAD: StateID = usize::MAX;
+
+/// `DEAD` as stored in `DFA::transitions`.
+pub(crate) const DEAD_ID: u32 = u32::MAX;
+
+impl DFA {
+    /// Builds a DFA from an NFA produced by `NFA::union` using subset
+    /// construction. Each DFA state accepts the rule picked by
+    /// `NFA::accepting_rule`.
+    pub fn from_nfa(nfa: &NFA, precedences: &[u8]) -> DFA {
+        let alphabet = nfa.alphabet.clone();
+        let contexts = context_indexes(nfa);
+        let rows = if nfa.contexts.is_some() {
+            Context::ALL.len()
+        } else {
+            1
+        };
+        // Only word boundaries still need the context before a state
+        let keep_prev = nfa.has_word_boundaries();
+
+        let mut transitions = vec![];
+        let mut accepts = vec![];
+        let mut ids = HashMap::new();
+        let mut pending = VecDeque::new();
+        let mut set = nfa.states();
+
+        let mut starts = vec![];
+        for &prev in Context::ALL[..rows].iter() {
+            nfa.initialize_states(&mut set, prev);
+            let key = (
+                set.ones().collect::<Vec<_>>(),
+                if keep_prev { prev } else { Context::Other },
+            );
+
+            let next_id = ids.len();
+            let id = *ids.entry(key.clone()).or_insert_with(|| {
+                pending.push_back(key);
+                next_id
+            });
+            starts.push(id);
+        }
+
+        while let Some((current, prev)) = pending.pop_front() {
+            let resolved = if nfa.contexts.is_some() {
+                Context::ALL
+                    .iter()
+                    .map(|&next| {
+                        nfa.resolve(current.iter().copied(), prev, next, &mut set);
+                        set.ones().collect::<Vec<_>>()
+                    })
+                    .collect::<Vec<_>>()
+            } else {
+                vec![current]
+            };
+
+            for states in resolved.iter() {
+                accepts.push(nfa.accepting_rule(states.iter().copied(), precedences));
+            }
+
+            for class in 0..alphabet.len() {
+                let current = &resolved[contexts[class]];
+                nfa.step_class(current.iter().copied(), class, &mut set);
+                if set.count_ones(..) == 0 {
+                    transitions.push(DEAD_ID);
+                    continue;
+                }
+
+                let prev = if keep_prev {
+                    nfa.context_of_class(class)
+                } else {
+                    Context::Other
+                };
+                let next = (set.ones().collect::<Vec<_>>(), prev);
+
+                let id = match ids.get(&next) {
+                    Some(id) => *id,
+                    None => {
+                        let id = ids.len();
+                        ids.insert(next.clone(), id);
+                        pending.push_back(next);
+                        id
+                    }
+                };
+                transitions.push(id as u32);
+            }
+        }
+
+        DFA {
+            alphabet,
+            contexts,
+            starts,
+            transitions: transitions.into(),
+            accepts,
+        }
+    }
+
+    /// Returns the start state for input that follows the char `prev`.
+    pub fn start(&self, prev: Option<char>) -> StateID {
+        match prev {
+            Some(c) => self.starts[self.contexts[self.alphabet.class_of(c)]],
+            None => self.starts[Context::Edge as usize],
+        }
+    }
+
+    /// Returns the rule accepted by `state` when followed by the context with
+    /// index `next`.
+    #[inline]
+    pub fn accept(&self, state: StateID, next: usize) -> Option<usize> {
+        self.accepts[state * self.starts.len() + next]
+    }
+
+    /// Returns the state `state` moves to on `class`, or `DEAD`.
+    #[inline]
+    pub fn transition(&self, state: StateID, class: usize) -> StateID {
+        match self.transitions[state * self.alphabet.len() + class] {
+            DEAD_ID => DEAD,
+            to => to as StateID,
+        }
+    }
+
+    /// Returns the rule and length in bytes of the longest non-empty match at
+    /// the start of the input yielding `symbols`, which follows the char
+    /// `prev`.
+    pub fn longest_match(
+        &self,
+        symbols: impl Iterator<Item = (usize, char)>,
+        prev: Option<char>,
+    ) -> Option<(usize, usize)> {
+        let mut state = self.start(prev);
+        let mut best = None;
+        let mut len = 0;
+
+        for (end, c) in symbols {
+            let class = self.alphabet.class_of(c);
+            if len > 0 {
+                if let Some(rule) = self.accept(state, self.contexts[class]) {
+                    best = Some((rule, len));
+                }
+            }
+
+            state = match self.transitions[state * self.alphabet.len() + class] {
+                DEAD_ID => return best,
+                to => to as StateID,
+            };
+            len = end;
+        }
+
+        if len > 0 {
+            if let Some(rule) = self.accept(state, Context::Edge as usize) {
+                best = Some((rule, len));
+            }
+        }
+
+        best
+    }
+
+    pub fn state_count(&self) -> usize {
+        self.accepts.len() / self.starts.len()
+    }
+}
+
+/// Returns the index of the context of each class of `nfa`, or all zeros when
+/// it has no look states.
+fn context_indexes(nfa: &NFA) -> Vec<usize> {
+    (0..nfa.alphabet.len())
+        .map(|class| match nfa.contexts {
+            Some(_) => nfa.context_of_class(class) as usize,
+            None => 0,
+        })
+        .collect()
+}
+
+#[cfg(all(test, feature = "regex-syntax"))]
+mod tests {
+    use super::DFA;
+    use crate::nfa::{byte_symbols, symbols, NFA};
+
+    fn dfa(patterns: &[&str]) -> DFA {
+        let nfas = patterns
+            .iter()
+            .map(|p| NFA::from_regex(p).unwrap())
+            .collect::<Vec<_>>();
+        let precedences = vec![0; nfas.len()];
+
+        DFA::from_nfa(&NFA::union(&nfas), &precedences)
+    }
+
+    #[test]
+    fn longest() {
+        let dfa = dfa(&["a", "a+", "b"]);
+
+        assert_eq!(dfa.longest_match(symbols("a"), None), Some((0, 1)));
+        assert_eq!(dfa.longest_match(symbols("aaab"), None), Some((1, 3)));
+        assert_eq!(dfa.longest_match(symbols("b"), None), Some((2, 1)));
+        assert_eq!(dfa.longest_match(symbols("c"), None), None);
+        assert_eq!(dfa.longest_match(symbols(""), None), None);
+    }
+
+    #[test]
+    fn precedence() {
+        let nfas = vec![NFA::from_regex("[a-z]+").unwrap(), NFA::from_literal("if")];
+        let dfa = DFA::from_nfa(&NFA::union(&nfas), &[0, 1]);
+
+        assert_eq!(dfa.longest_match(symbols("if"), None), Some((1, 2)));
+        assert_eq!(dfa.longest_match(symbols("iffy"), None), Some((0, 4)));
+        assert_eq!(dfa.longest_match(symbols("i"), None), Some((0, 1)));
+    }
+
+    #[test]
+    fn negated_classes() {
+        let dfa = dfa(&["[^a]+", "a"]);
+
+        assert_eq!(
+            dfa.longest_match(symbols("bcd\u{10FFFF}a"), None),
+            Some((0, 7))
+        );
+        assert_eq!(dfa.longest_match(symbols("a"), None), Some((1, 1)));
+    }
+
+    #[test]
+    fn empty_matches_are_ignored() {
+        let dfa = dfa(&["a*"]);
+
+        assert_eq!(dfa.longest_match(symbols("b"), None), None);
+        assert_eq!(dfa.longest_match(symbols("aab"), None), Some((0, 2)));
+    }
+
+    #[test]
+    fn bytes() {
+        let nfas = vec![
+            NFA::from_regex_bytes(r"\w+").unwrap(),
+            NFA::from_regex_bytes(r"(?-u:[\x80-\xFF])").unwrap(),
+            NFA::from_literal_bytes("é".as_bytes()),
+        ];
+        let dfa = DFA::from_nfa(&NFA::union(&nfas), &[0, 0, 1]);
+
+        assert_eq!(
+            dfa.longest_match(byte_symbols("ünï x".as_bytes()), None),
+            Some((0, 5))
+        );
+        assert_eq!(
+            dfa.longest_match(byte_symbols(b"ab\xFFc"), None),
+            Some((0, 2))
+        );
+        assert_eq!(
+            dfa.longest_match(byte_symbols(b"\xFFc"), None),
+            Some((1, 1))
+        );
+        assert_eq!(
+            dfa.longest_match(byte_symbols("é!".as_bytes()), None),
+            Some((2, 2))
+        );
+        assert_eq!(dfa.longest_match(byte_symbols(b"!"), None), None);
+    }
+
+    #[test]
+    fn assertions() {
+        let dfa = dfa(&["^a", r"a\b", "a$", "[a-z]"]);
+        let minimized = dfa.minimize();
+
+        let cases = [
+            ("a", None, Some((0, 1))),
+            ("ab", Some('\n'), Some((0, 1))),
+            ("a b", Some('x'), Some((1, 1))),
+            ("ab", Some('x'), Some((3, 1))),
+            ("a\nb", Some(' '), Some((1, 1))),
+            ("a", Some('x'), Some((1, 1))),
+            ("aé", Some(' '), Some((3, 1))),
+        ];
+        for (input, prev, expected) in cases.iter() {
+            assert_eq!(dfa.longest_match(symbols(input), *prev), *expected);
+            assert_eq!(minimized.longest_match(symbols(input), *prev), *expected);
+        }
+    }
+}
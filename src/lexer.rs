@@ -1,198 +1,559 @@
-use std::char;
-use std::iter::FromIterator;
-
-use fixedbitset::FixedBitSet;
-use hashbrown::{HashMap, HashSet};
-use regex::Regex;
+use std::ops::{Deref, DerefMut, Index, Range};
+use std::sync::Arc;
+
+use crate::dfa::{LazyDFA, DFA};
+use crate::lexicon::{Automaton, ByteLexicon, Lexicon, Mode, ModeID, ModeStack};
+use crate::nfa::{byte_symbols, symbols, PikeVM, NFA};
+
+use self::input::Input;
+
+/// Lexes `&str` input with a `Lexicon`, yielding tokens whose kinds are of
+/// type `K`. `ByteLexer` derefs to a `Lexer` over `[u8]` instead.
+///
+/// Creating a lexer does not allocate, as it shares the automata of the
+/// lexicon. The state a lazy DFA or Pike VM needs while matching is created
+/// the first time a mode is matched in, and kept across `reset_with`.
+pub struct Lexer<'input, K = usize, T: ?Sized = str> {
+    lexicon: Lexicon<K>,
+    input: &'input T,
+    pos: Position,
+    last_token: Option<(usize, Range<usize>)>,
+    /// The matcher of each mode matched in so far.
+    matchers: Vec<Option<Matcher>>,
+    modes: ModeStack,
+    preserve_trivia: bool,
+    trivia: Option<Trivia>,
+}
 
-use crate::lexicon::{Lexicon, RuleID, RuleKind};
+/// Lexes `&[u8]` input with a `ByteLexicon`, without requiring it to be valid
+/// UTF-8. Columns count chars, with each byte of invalid UTF-8 counting as one.
+pub struct ByteLexer<'input, K = usize>(Lexer<'input, K, [u8]>);
 
-pub struct Lexer<'input> {
-    input: &'input str,
-    offset: usize,
-    pos: Position,
-    rules: Vec<Rule>,
-    matches: Vec<(usize, usize)>,
-    ignore_chars: HashSet<char>,
-    prefixes: HashMap<char, FixedBitSet>,
+#[derive(Debug, PartialEq)]
+pub enum Next<'input, K = usize, T: ?Sized = str> {
+    Token(Token<'input, K, T>),
+    Error(Error<'input, T>, Span),
 }
 
+/// A match of a rule, along with the kind it was given in the lexicon.
 #[derive(Debug, PartialEq)]
-pub enum Next<'input> {
-    Token(usize, &'input str, Position),
-    Error(Error<'input>, Position),
+pub struct Token<'input, K = usize, T: ?Sized = str> {
+    pub kind: K,
+    pub text: &'input T,
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq)]
-pub enum Error<'input> {
-    UnexpectedChar(&'input str),
+pub enum Error<'input, T: ?Sized = str> {
+    UnexpectedChar(&'input T),
+}
+
+/// The capture groups of the pattern that matched a token, as slices of the
+/// token's text.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Captures<'input, T: ?Sized = str> {
+    text: &'input T,
+    groups: Vec<Option<(usize, usize)>>,
+    names: Arc<Vec<Option<String>>>,
 }
 
+/// The ignored chars and skipped matches around a token or error, as byte
+/// ranges of the input.
+///
+/// Trailing trivia runs up to and including the end of the line the token
+/// ends on, and leading trivia holds the rest. Concatenating the leading
+/// trivia, text and trailing trivia of every token and error, followed by the
+/// leading trivia left at the end of the input, gives back the input.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trivia {
+    pub leading: Range<usize>,
+    pub trailing: Range<usize>,
+}
+
+/// A place in the input, as a byte offset along with the line and column it
+/// falls on. Lines and columns start at 1, and columns count chars.
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Position {
+    offset: usize,
     line: u32,
     col: u32,
 }
 
-struct Rule {
-    id: usize,
-    precedence: u8,
-    pattern: Pattern,
+/// The extent of a token or error in the input, from its first char up to
+/// but not including `end`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
 }
 
-enum Pattern {
-    Literal(String),
-    Regex(Regex),
+enum Matcher {
+    Dfa(Arc<DFA>),
+    Lazy(Box<LazyDFA>),
+    PikeVM(Box<PikeVM>),
 }
 
-impl<'input> Lexer<'input> {
-    pub fn new(lexicon: &Lexicon, input: &'input str) -> Self {
-        let rules = lexicon
-            .rules
-            .iter()
-            .map(|r| {
-                let pattern = if r.kind == RuleKind::Literal {
-                    Pattern::Literal(r.pattern.clone())
-                } else {
-                    let anchored = format!("\\A(?:{})", r.pattern);
-                    let regex = Regex::new(&anchored).unwrap();
+impl<'input, K: Copy> Lexer<'input, K> {
+    pub fn new(lexicon: &Lexicon<K>, input: &'input str) -> Self {
+        Lexer::with_lexicon(lexicon, input)
+    }
+}
 
-                    Pattern::Regex(regex)
-                };
+impl<'input, K: Copy> ByteLexer<'input, K> {
+    pub fn new(lexicon: &ByteLexicon<K>, input: &'input [u8]) -> Self {
+        ByteLexer(Lexer::with_lexicon(&lexicon.0, input))
+    }
 
-                Rule {
-                    id: r.id,
-                    precedence: r.precedence,
-                    pattern,
-                }
-            })
-            .collect::<Vec<_>>();
-
-        let ignore_chars = HashSet::from_iter(lexicon.ignore_chars.iter().copied());
-
-        let mut prefixes = HashMap::new();
-        for (rule_idx, rule) in lexicon.rules.iter().enumerate() {
-            for c in rule.starting_chars.iter() {
-                prefixes
-                    .entry(*c)
-                    .or_insert_with(|| FixedBitSet::with_capacity(rules.len()))
-                    .insert(rule_idx);
-            }
-        }
+    /// Keeps track of the trivia around each token and error, as with
+    /// `Lexer::preserve_trivia`.
+    pub fn preserve_trivia(self) -> Self {
+        ByteLexer(self.0.preserve_trivia())
+    }
+}
+
+impl<'input, K> Deref for ByteLexer<'input, K> {
+    type Target = Lexer<'input, K, [u8]>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
 
+impl<'input, K> DerefMut for ByteLexer<'input, K> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<'input, K: Copy, T: Input + ?Sized> Lexer<'input, K, T> {
+    fn with_lexicon(lexicon: &Lexicon<K>, input: &'input T) -> Self {
         Self {
+            lexicon: lexicon.clone(),
             input,
-            offset: 0,
-            pos: Position { line: 1, col: 1 },
-            rules,
-            matches: Vec::with_capacity(lexicon.rules.len()),
-            ignore_chars,
-            prefixes,
+            pos: Position::new(0, 1, 1),
+            last_token: None,
+            matchers: vec![],
+            modes: ModeStack::new(),
+            preserve_trivia: false,
+            trivia: None,
         }
     }
 
-    fn advance(&mut self, ch: char) {
-        self.offset += ch.len_utf8();
+    /// Keeps track of the trivia around each token and error, for `trivia`
+    /// to return. Trailing trivia is consumed along with the token before it.
+    pub fn preserve_trivia(mut self) -> Self {
+        self.preserve_trivia = true;
 
-        if ch == '\n' {
-            self.pos.line += 1;
-            self.pos.col = 1;
-        } else {
-            self.pos.col += 1;
-        }
+        self
     }
 
-    fn best_match(&self, matches: &[(usize, usize)]) -> Option<(RuleID, usize)> {
-        if matches.is_empty() {
-            return None;
-        }
-
-        let mut precedence = 0;
-        let mut match_len = 0;
-        let mut rule_id = 0;
+    fn advance(&mut self, ch: Option<char>, len: usize) {
+        self.pos.advance(ch, len);
+    }
 
-        for (i, len) in matches.iter() {
-            let rule = &self.rules[*i];
-            if *len > match_len || (*len == match_len && rule.precedence > precedence) {
-                precedence = rule.precedence;
-                rule_id = rule.id;
-                match_len = *len;
+    /// Advances past ignored chars, returning the char after them and its
+    /// length, or `None` at the end of the input.
+    fn skip_ignored_chars(&mut self) -> Option<(Option<char>, usize)> {
+        loop {
+            let (ch, len) = self.input.char_at(self.pos.offset)?;
+            match ch {
+                Some(ch) if self.lexicon.ignore_chars.contains(&ch) => self.advance(Some(ch), len),
+                _ => return Some((ch, len)),
             }
         }
-
-        Some((rule_id, match_len))
     }
 
-    pub fn next(&mut self) -> Option<Next> {
-        let c = loop {
-            let ch = self.input[self.offset..].chars().nth(0)?;
-            if !self.ignore_chars.contains(&ch) {
-                break ch;
+    /// Advances past the ignored chars and skipped matches after a token, up
+    /// to and including the end of its line.
+    fn skip_trailing_trivia(&mut self) {
+        while let Some((ch, len)) = self.input.char_at(self.pos.offset) {
+            if let Some(ch) = ch.filter(|ch| self.lexicon.ignore_chars.contains(ch)) {
+                self.advance(Some(ch), len);
+                if ch == '\n' {
+                    return;
+                }
+                continue;
             }
 
-            self.advance(ch);
-        };
+            match self.longest_match() {
+                Some((rule, len)) if self.lexicon.rules[rule].kind.is_none() => {
+                    let line = self.pos.line;
+                    self.consume(rule, len);
+                    if self.pos.line != line {
+                        return;
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
 
-        let input = &self.input[self.offset..];
-        let pos = self.pos;
-
-        let rule_indicies = match self.prefixes.get(&c) {
-            Some(indicies) => indicies,
-            None => {
-                self.advance(c);
-                return Some(Next::Error(
-                    Error::UnexpectedChar(&input[0..c.len_utf8()]),
-                    pos,
-                ));
+    /// Returns the rule and length of the longest match at the current
+    /// offset, leaving out any trailing context.
+    fn longest_match(&mut self) -> Option<(usize, usize)> {
+        let input = &self.input[self.pos.offset..];
+        let prev = self.input.symbol_before(self.pos.offset);
+
+        let mode = self.mode();
+        let (rule, len) = self.matcher(mode).longest_match(input.symbols(), prev)?;
+        let rule = self.lexicon.modes[mode].rules[rule];
+
+        let len = match &self.lexicon.rules[rule].trailing_context {
+            Some(nfa) => {
+                let next = input.symbol_at(len);
+                let cut = nfa
+                    .cut(input[0..len].symbols(), prev, next)
+                    .filter(|&cut| cut > 0);
+                // `build` rejects empty heads, so only a lexicon loaded from
+                // corrupt data can fail to be cut
+                debug_assert!(
+                    cut.is_some() || self.lexicon.loaded,
+                    "no cut in a trailing-context match"
+                );
+                cut.unwrap_or(len)
             }
+            None => len,
         };
 
-        self.matches.clear();
+        Some((rule, len))
+    }
+
+    /// Returns the matcher of `mode`, creating it if this is the first match
+    /// in the mode.
+    fn matcher(&mut self, mode: ModeID) -> &mut Matcher {
+        if self.matchers.len() <= mode {
+            self.matchers.resize_with(mode + 1, || None);
+        }
+
+        let lexicon = &self.lexicon;
+        self.matchers[mode].get_or_insert_with(|| Matcher::new(lexicon, &lexicon.modes[mode]))
+    }
 
-        for i in rule_indicies.ones() {
-            let rule = &self.rules[i];
+    /// Advances past a match of `rule` and applies its action.
+    fn consume(&mut self, rule: usize, len: usize) {
+        if let Some(action) = self.lexicon.rules[rule].action {
+            action.apply(&mut self.modes);
+        }
 
-            match &rule.pattern {
-                Pattern::Literal(literal) => {
-                    if c.len_utf8() == literal.len() || *literal == input[..literal.len()] {
-                        self.matches.push((i, literal.len()));
+        let end = self.pos.offset + len;
+        while let Some((ch, len)) = self.input[self.pos.offset..end].char_at(0) {
+            self.advance(ch, len);
+        }
+    }
+
+    /// Returns the capture groups of the last token returned by `next`, or
+    /// `None` if it returned an error or nothing yet.
+    pub fn captures(&self) -> Option<Captures<'input, T>> {
+        let input = self.input;
+        let (rule, range) = self.last_token.clone()?;
+        let prev = input.symbol_before(range.start);
+        let next = input.symbol_at(range.end);
+        let text = &input[range];
+
+        Captures::new(
+            &self.lexicon.rules[rule].captures,
+            text,
+            text.symbols(),
+            prev,
+            next,
+        )
+    }
+
+    /// Returns the trivia around the last token or error returned by `next`,
+    /// if trivia is preserved. Once `next` returns `None`, the leading trivia
+    /// holds what was left at the end of the input.
+    pub fn trivia(&self) -> Option<Trivia> {
+        self.trivia.clone()
+    }
+
+    /// Returns the current mode.
+    pub fn mode(&self) -> ModeID {
+        self.modes.current()
+    }
+
+    pub fn reset(&mut self) {
+        self.pos = Position::new(0, 1, 1);
+        self.last_token = None;
+        self.modes.clear();
+        self.trivia = None;
+    }
+
+    /// Starts lexing `input` from the beginning, keeping the state built up
+    /// while matching the previous input.
+    pub fn reset_with(&mut self, input: &'input T) {
+        self.input = input;
+        self.reset();
+    }
+}
+
+impl<'input, K: Copy, T: Input + ?Sized> Iterator for Lexer<'input, K, T> {
+    type Item = Next<'input, K, T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let input = self.input;
+        let leading = self.pos.offset;
+        self.last_token = None;
+        self.trivia = None;
+
+        loop {
+            let (c, c_len) = match self.skip_ignored_chars() {
+                Some(c) => c,
+                None => {
+                    if self.preserve_trivia {
+                        self.trivia = Some(Trivia {
+                            leading: leading..self.pos.offset,
+                            trailing: self.pos.offset..self.pos.offset,
+                        });
                     }
+                    return None;
                 }
-                Pattern::Regex(regex) => {
-                    if let Some(m) = regex.find_at(input, 0) {
-                        self.matches.push((i, m.end()));
-                    }
+            };
+
+            let start = self.pos;
+
+            let next = match self.longest_match() {
+                Some((rule, len)) => {
+                    self.consume(rule, len);
+                    let kind = match self.lexicon.rules[rule].kind {
+                        Some(kind) => kind,
+                        None => continue,
+                    };
+
+                    let span = Span::new(start, self.pos);
+                    self.last_token = Some((rule, span.range()));
+                    Next::Token(Token {
+                        kind,
+                        text: &input[span.range()],
+                        span,
+                    })
+                }
+                None => {
+                    self.advance(c, c_len);
+                    let span = Span::new(start, self.pos);
+                    Next::Error(Error::UnexpectedChar(&input[span.range()]), span)
                 }
+            };
+
+            if self.preserve_trivia {
+                let end = self.pos.offset;
+                self.skip_trailing_trivia();
+                self.trivia = Some(Trivia {
+                    leading: leading..start.offset,
+                    trailing: end..self.pos.offset,
+                });
             }
+
+            return Some(next);
         }
+    }
+}
+
+impl<'input, K: Copy> Iterator for ByteLexer<'input, K> {
+    type Item = Next<'input, K, [u8]>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+impl<'input, T: ?Sized + Index<Range<usize>, Output = T>> Captures<'input, T> {
+    fn new(
+        nfa: &Option<NFA>,
+        text: &'input T,
+        symbols: impl Iterator<Item = (usize, char)>,
+        prev: Option<char>,
+        next: Option<char>,
+    ) -> Option<Self> {
+        let (groups, names) = match nfa {
+            Some(nfa) => (
+                nfa.captures(symbols, prev, next)?,
+                nfa.capture_names.clone(),
+            ),
+            None => (vec![], Arc::new(vec![])),
+        };
+
+        Some(Captures {
+            text,
+            groups,
+            names,
+        })
+    }
 
-        let best = self.best_match(&self.matches);
-        if best.is_none() {
-            self.advance(c);
-            return Some(Next::Error(
-                Error::UnexpectedChar(&input[0..c.len_utf8()]),
-                pos,
-            ));
+    /// Returns the text matched by group `i`, where group 0 is the whole
+    /// token. Returns `None` if the group did not take part in the match.
+    pub fn get(&self, i: usize) -> Option<&'input T> {
+        if i == 0 {
+            return Some(self.text);
         }
 
-        let (rule_id, len) = best.unwrap();
-        let text = &self.input[self.offset..(self.offset + len)];
+        let (start, end) = (*self.groups.get(i - 1)?)?;
+        Some(&self.text[start..end])
+    }
+
+    /// Returns the text matched by the group with the given name.
+    pub fn name(&self, name: &str) -> Option<&'input T> {
+        let i = self.names.iter().position(|n| n.as_deref() == Some(name))?;
+        self.get(i + 1)
+    }
+}
+
+impl Matcher {
+    fn new<K>(lexicon: &Lexicon<K>, mode: &Mode) -> Matcher {
+        let precedences = || {
+            mode.rules
+                .iter()
+                .map(|&i| lexicon.rules[i].precedence)
+                .collect()
+        };
 
-        for c in input[..len].chars() {
-            self.advance(c);
+        match &mode.automaton {
+            Automaton::Dfa(dfa) => Matcher::Dfa(dfa.clone()),
+            Automaton::Lazy {
+                nfa,
+                prefixes,
+                cache_size,
+            } => Matcher::Lazy(Box::new(LazyDFA::new(
+                nfa.clone(),
+                precedences(),
+                prefixes.clone(),
+                *cache_size,
+            ))),
+            Automaton::PikeVM { nfa, prefixes } => Matcher::PikeVM(Box::new(PikeVM::new(
+                nfa.clone(),
+                precedences(),
+                prefixes.clone(),
+            ))),
         }
+    }
 
-        Some(Next::Token(rule_id, text, pos))
+    fn longest_match<I>(&mut self, symbols: I, prev: Option<char>) -> Option<(usize, usize)>
+    where
+        I: Iterator<Item = (usize, char)> + Clone,
+    {
+        match self {
+            Matcher::Dfa(dfa) => dfa.longest_match(symbols, prev),
+            Matcher::Lazy(lazy) => lazy.longest_match(symbols, prev),
+            Matcher::PikeVM(vm) => vm.longest_match(symbols, prev),
+        }
     }
+}
 
-    pub fn reset(&mut self) {
-        self.offset = 0;
-        self.pos = Position::new(1, 1);
+mod input {
+    use std::ops::{Index, Range, RangeFrom};
+
+    use super::{byte_symbols, decode_utf8, symbols};
+
+    /// The types of input a `Lexer` can lex.
+    pub trait Input:
+        Index<Range<usize>, Output = Self> + Index<RangeFrom<usize>, Output = Self>
+    {
+        /// Decodes the char at `offset` along with its length in bytes.
+        /// Bytes that do not begin valid UTF-8 decode to `None`.
+        fn char_at(&self, offset: usize) -> Option<(Option<char>, usize)>;
+
+        /// Returns the symbol the automata see before `offset`.
+        fn symbol_before(&self, offset: usize) -> Option<char>;
+
+        /// Returns the symbol the automata see at `offset`.
+        fn symbol_at(&self, offset: usize) -> Option<char>;
+
+        /// Returns the symbols the automata see, each with the offset just
+        /// past it.
+        fn symbols(&self) -> impl Iterator<Item = (usize, char)> + Clone + '_;
+    }
+
+    impl Input for str {
+        fn char_at(&self, offset: usize) -> Option<(Option<char>, usize)> {
+            let c = self[offset..].chars().next()?;
+            Some((Some(c), c.len_utf8()))
+        }
+
+        fn symbol_before(&self, offset: usize) -> Option<char> {
+            self[..offset].chars().next_back()
+        }
+
+        fn symbol_at(&self, offset: usize) -> Option<char> {
+            self[offset..].chars().next()
+        }
+
+        fn symbols(&self) -> impl Iterator<Item = (usize, char)> + Clone + '_ {
+            symbols(self)
+        }
+    }
+
+    impl Input for [u8] {
+        fn char_at(&self, offset: usize) -> Option<(Option<char>, usize)> {
+            decode_utf8(&self[offset..])
+        }
+
+        fn symbol_before(&self, offset: usize) -> Option<char> {
+            self[..offset].last().map(|&b| char::from(b))
+        }
+
+        fn symbol_at(&self, offset: usize) -> Option<char> {
+            self.get(offset).map(|&b| char::from(b))
+        }
+
+        fn symbols(&self) -> impl Iterator<Item = (usize, char)> + Clone + '_ {
+            byte_symbols(self)
+        }
+    }
+}
+
+/// Decodes the char at the start of `input` along with its length in bytes.
+/// Bytes that do not begin valid UTF-8 decode to `None` with a length of 1.
+fn decode_utf8(input: &[u8]) -> Option<(Option<char>, usize)> {
+    let len = match *input.first()? {
+        b if b < 0x80 => return Some((Some(char::from(b)), 1)),
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF7 => 4,
+        _ => return Some((None, 1)),
+    };
+
+    match input.get(..len).map(std::str::from_utf8) {
+        Some(Ok(s)) => Some((s.chars().next(), len)),
+        _ => Some((None, 1)),
     }
 }
 
 impl Position {
-    pub fn new(line: u32, col: u32) -> Position {
-        Position { line, col }
+    pub fn new(offset: usize, line: u32, col: u32) -> Position {
+        Position { offset, line, col }
+    }
+
+    /// Moves past `ch`, which is `len` bytes long.
+    pub(crate) fn advance(&mut self, ch: Option<char>, len: usize) {
+        self.offset += len;
+
+        if ch == Some('\n') {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    pub fn col(&self) -> u32 {
+        self.col
+    }
+}
+
+impl Span {
+    pub fn new(start: Position, end: Position) -> Span {
+        Span { start, end }
+    }
+
+    /// Returns the byte range of the input the span covers, for slicing it.
+    pub fn range(&self) -> Range<usize> {
+        self.start.offset..self.end.offset
     }
 }
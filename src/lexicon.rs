@@ -1,78 +1,415 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
 use hashbrown::HashSet;
-use std::iter::FromIterator;
 
-use crate::nfa::{CompileError, NFA};
+use crate::dfa::DFA;
+use crate::nfa::{CompileError, Prefixes, NFA};
+
+/// The rules of a lexer, compiled into automata. Tokens matched by a rule are
+/// tagged with the kind of type `K` it was added with.
+///
+/// Lexers share the automata of the lexicon they are created with, as do
+/// clones of it. A lexicon is `Send` and `Sync` when `K` is, so lexers on
+/// different threads can share one, while each keeps its own matching state.
+pub struct Lexicon<K = usize> {
+    pub(crate) ignore_chars: Arc<HashSet<char>>,
+    pub(crate) rules: Arc<Vec<Rule<K>>>,
+    pub(crate) modes: Arc<Vec<Mode>>,
+    pub(crate) stats: Stats,
+    /// Whether the lexicon was loaded from bytes, which may be corrupt,
+    /// rather than built.
+    pub(crate) loaded: bool,
+}
+
+/// A `Lexicon` whose automaton matches the bytes of UTF-8 encoded input, for
+/// use with a `ByteLexer`.
+pub struct ByteLexicon<K = usize>(pub(crate) Lexicon<K>);
 
-pub struct Lexicon {
-    pub(crate) ignore_chars: HashSet<char>,
-    pub(crate) rules: Vec<Rule>,
+/// Sizes of the automata built for a `Lexicon`, summed over its modes. The DFA
+/// state counts are zero when no DFA is built up front.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stats {
+    pub nfa_states: usize,
+    pub dfa_states: usize,
+    pub minimized_dfa_states: usize,
 }
 
-#[derive(Default)]
-pub struct LexiconBuilder {
+pub struct LexiconBuilder<K = usize> {
     ignore_chars: HashSet<char>,
-    rules: Vec<(RuleID, RuleKind, String)>,
+    rules: Vec<RuleDef<K>>,
+    modes: Vec<ModeID>,
+    engine: Engine,
+}
+
+/// A rule as passed to the builder, before it is compiled.
+struct RuleDef<K> {
+    id: Option<K>,
+    kind: RuleKind,
+    source: String,
+    trailing: Option<String>,
+    case_insensitive: bool,
+    modes: Vec<ModeID>,
+    action: Option<Action>,
+    precedence: Option<u8>,
+}
+
+/// Changes the mode of a `Lexer` after it matches a rule, like `BEGIN` and
+/// the start condition stack of flex.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    /// Enters a mode, returning to the current one on `Pop`.
+    Push(ModeID),
+    /// Returns to the mode that was current before the last `Push`. Popping
+    /// the initial mode does nothing.
+    Pop,
+    /// Replaces the current mode.
+    Switch(ModeID),
 }
 
-pub(crate) struct Rule {
-    pub(crate) id: RuleID,
-    pub(crate) kind: RuleKind,
+#[derive(Clone, Copy, Default)]
+enum Engine {
+    #[default]
+    Dfa,
+    Lazy(usize),
+    PikeVM,
+}
+
+pub(crate) enum Automaton {
+    Dfa(Arc<DFA>),
+    Lazy {
+        nfa: NFA,
+        prefixes: Arc<Prefixes>,
+        cache_size: usize,
+    },
+    PikeVM {
+        nfa: NFA,
+        prefixes: Arc<Prefixes>,
+    },
+}
+
+/// The rules active in a mode, and the automaton matching them.
+pub(crate) struct Mode {
+    /// The index of each rule of the automaton in `Lexicon::rules`.
+    pub(crate) rules: Vec<usize>,
+    pub(crate) automaton: Automaton,
+}
+
+pub(crate) struct Rule<K> {
+    /// The kind of the rule's tokens, or `None` if its matches are skipped
+    /// rather than returned as tokens.
+    pub(crate) kind: Option<K>,
     pub(crate) precedence: u8,
-    pub(crate) pattern: String,
-    pub(crate) starting_chars: HashSet<char>,
+    pub(crate) action: Option<Action>,
+    /// The NFA of a rule with trailing context, used to find where its
+    /// matches end.
+    pub(crate) trailing_context: Option<NFA>,
+    /// The NFA of a pattern with capture groups, used to find them in its
+    /// matches.
+    pub(crate) captures: Option<NFA>,
 }
 
 #[derive(Clone, Copy, PartialEq, PartialOrd)]
 pub(crate) enum RuleKind {
     Pattern,
+    ShortestPattern,
     Literal,
 }
 
-pub type RuleID = usize;
+/// Identifies a set of rules that are active together. Lexers start out in
+/// `INITIAL`, which is also the mode of rules added before any call to
+/// `LexiconBuilder::modes`.
+pub type ModeID = usize;
+
+pub const INITIAL: ModeID = 0;
 
-#[derive(Debug)]
-pub enum Error {
+/// A token kind whose rules are given by attributes on its variants, as
+/// implemented by `#[derive(Lexicon)]` from the `lexer-derive` crate.
+pub trait TokenKind: Copy + Debug + Sized {
+    /// Returns a builder with the rules of every variant added.
+    fn builder() -> LexiconBuilder<Self>;
+
+    /// Builds the lexicon of the kind. The derive checks each rule as it
+    /// expands, so this only panics for hand-written implementations.
+    fn lexicon() -> Lexicon<Self> {
+        match Self::builder().build() {
+            Ok(lexicon) => lexicon,
+            Err(e) => panic!("invalid rules for token kind: {:?}", e),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error<K = usize> {
     InvalidRegex(CompileError),
-    EmptyLiteral(usize),
+    EmptyLiteral(K),
+    /// The pattern of a rule with trailing context matches the empty string,
+    /// so the rule could match nothing but its trailing context.
+    EmptyTrailingContextHead(K),
+    EmptyShortestPattern(K),
+}
+
+/// The stack of modes of a lexer. The bottom mode is kept apart, so that the
+/// stack only allocates once a mode is pushed.
+#[derive(Clone, Debug)]
+pub(crate) struct ModeStack {
+    bottom: ModeID,
+    pushed: Vec<ModeID>,
 }
 
-impl LexiconBuilder {
+impl Action {
+    /// Applies the action to a stack of modes.
+    pub(crate) fn apply(self, modes: &mut ModeStack) {
+        match self {
+            Action::Push(mode) => modes.pushed.push(mode),
+            Action::Pop => {
+                modes.pushed.pop();
+            }
+            Action::Switch(mode) => match modes.pushed.last_mut() {
+                Some(last) => *last = mode,
+                None => modes.bottom = mode,
+            },
+        }
+    }
+}
+
+impl ModeStack {
+    pub(crate) fn new() -> ModeStack {
+        ModeStack {
+            bottom: INITIAL,
+            pushed: vec![],
+        }
+    }
+
+    /// Returns the current mode.
+    pub(crate) fn current(&self) -> ModeID {
+        self.pushed.last().copied().unwrap_or(self.bottom)
+    }
+
+    /// Returns to `INITIAL`, keeping the memory allocated for the stack.
+    pub(crate) fn clear(&mut self) {
+        self.bottom = INITIAL;
+        self.pushed.clear();
+    }
+}
+
+impl<K> Lexicon<K> {
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+}
+
+impl<K> ByteLexicon<K> {
+    pub fn stats(&self) -> Stats {
+        self.0.stats
+    }
+}
+
+impl<K> Clone for Lexicon<K> {
+    fn clone(&self) -> Self {
+        Lexicon {
+            ignore_chars: self.ignore_chars.clone(),
+            rules: self.rules.clone(),
+            modes: self.modes.clone(),
+            stats: self.stats,
+            loaded: self.loaded,
+        }
+    }
+}
+
+impl<K> Clone for ByteLexicon<K> {
+    fn clone(&self) -> Self {
+        ByteLexicon(self.0.clone())
+    }
+}
+
+impl<K: Copy> Default for LexiconBuilder<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Copy> LexiconBuilder<K> {
     pub fn new() -> Self {
         Self {
             ignore_chars: HashSet::new(),
             rules: vec![],
+            modes: vec![INITIAL],
+            engine: Engine::Dfa,
         }
     }
 
-    pub fn build(self) -> Result<Lexicon, Error> {
+    pub fn build(self) -> Result<Lexicon<K>, Error<K>> {
+        self.build_with(false).map_err(|(_, e)| e)
+    }
+
+    /// Builds a lexicon for lexing `&[u8]` input that may not be valid UTF-8.
+    ///
+    /// Chars in patterns and literals match their UTF-8 encoding, while
+    /// patterns may also match arbitrary bytes with byte literals and classes
+    /// outside of Unicode mode, such as `(?-u:\xFF)` or `(?-u:[\x00-\x7F])`.
+    pub fn build_bytes(self) -> Result<ByteLexicon<K>, Error<K>> {
+        self.build_with(true).map(ByteLexicon).map_err(|(_, e)| e)
+    }
+
+    /// Builds a lexicon, returning the index of the rule an error is in along
+    /// with the error.
+    pub(crate) fn build_with(self, bytes: bool) -> Result<Lexicon<K>, (usize, Error<K>)> {
         let mut rules = vec![];
-        for (id, kind, pattern) in self.rules {
-            let starting_chars = match kind {
-                RuleKind::Pattern => NFA::from_regex(&pattern)
-                    .map_err(Error::InvalidRegex)?
-                    .starting_chars(),
+        let mut nfas = vec![];
+        let compile = |pattern: &str, case_insensitive, rule| {
+            if case_insensitive {
+                NFA::from_regex_case_insensitive(pattern, bytes)
+            } else if bytes {
+                NFA::from_regex_bytes(pattern)
+            } else {
+                NFA::from_regex(pattern)
+            }
+            .map_err(|e| (rule, Error::InvalidRegex(e)))
+        };
+
+        let mut rule_modes = vec![];
+        let mut last_mode = INITIAL;
+        for (index, rule) in self.rules.into_iter().enumerate() {
+            // Only skip rules lack an id, and they are never literals or
+            // patterns with trailing context.
+            let RuleDef { id, kind, .. } = rule;
+            let actions = rule.action.iter().filter_map(|action| match action {
+                Action::Push(mode) | Action::Switch(mode) => Some(*mode),
+                Action::Pop => None,
+            });
+            last_mode = rule
+                .modes
+                .iter()
+                .copied()
+                .chain(actions)
+                .fold(last_mode, usize::max);
+            rule_modes.push(rule.modes);
+
+            let nfa = match kind {
+                RuleKind::Pattern | RuleKind::ShortestPattern => {
+                    compile(&rule.source, rule.case_insensitive, index)?
+                }
                 RuleKind::Literal => {
-                    let c = pattern.chars().nth(0).ok_or(Error::EmptyLiteral(id))?;
-                    HashSet::from_iter(std::iter::once(c))
+                    if rule.source.is_empty() {
+                        return Err((index, Error::EmptyLiteral(id.unwrap())));
+                    }
+                    if rule.case_insensitive {
+                        NFA::from_literal_case_insensitive(&rule.source, bytes)
+                    } else if bytes {
+                        NFA::from_literal_bytes(rule.source.as_bytes())
+                    } else {
+                        NFA::from_literal(&rule.source)
+                    }
                 }
             };
-            let precedence = if kind == RuleKind::Literal { 1 } else { 0 };
+            let precedence = match rule.precedence {
+                Some(precedence) => precedence,
+                None if kind == RuleKind::Literal => 1,
+                None => 0,
+            };
+
+            let captures = if nfa.capture_count() > 0 {
+                Some(nfa.clone())
+            } else {
+                None
+            };
+            let nfa = if kind == RuleKind::ShortestPattern {
+                if nfa.matches("", &mut nfa.execution_state()).is_some() {
+                    return Err((index, Error::EmptyShortestPattern(id.unwrap())));
+                }
+
+                nfa.shortest()
+                    .map_err(|e| (index, Error::InvalidRegex(e)))?
+            } else {
+                nfa
+            };
+
+            let (nfa, trailing_context) = match rule.trailing {
+                Some(trailing) => {
+                    if nfa.matches("", &mut nfa.execution_state()).is_some() {
+                        return Err((index, Error::EmptyTrailingContextHead(id.unwrap())));
+                    }
+
+                    let nfa = NFA::with_trailing_context(&nfa, &compile(&trailing, false, index)?);
+                    (nfa.clone(), Some(nfa))
+                }
+                None => (nfa, None),
+            };
 
             rules.push(Rule {
-                id,
-                kind,
+                kind: id,
                 precedence,
-                pattern,
-                starting_chars,
+                action: rule.action,
+                trailing_context,
+                captures,
+            });
+            nfas.push(nfa);
+        }
+
+        let mut stats = Stats {
+            nfa_states: 0,
+            dfa_states: 0,
+            minimized_dfa_states: 0,
+        };
+
+        let mut modes = vec![];
+        for mode in 0..=last_mode {
+            let indexes = (0..rules.len())
+                .filter(|&i| rule_modes[i].contains(&mode))
+                .collect::<Vec<_>>();
+            let nfas = indexes.iter().map(|&i| nfas[i].clone()).collect::<Vec<_>>();
+
+            let nfa = NFA::union(&nfas);
+            stats.nfa_states += nfa.states.len();
+
+            let prefixes = || {
+                let starting_ranges = nfas.iter().map(|n| n.starting_ranges()).collect::<Vec<_>>();
+                Arc::new(Prefixes::new(&starting_ranges))
+            };
+
+            let automaton = match self.engine {
+                Engine::Lazy(cache_size) => Automaton::Lazy {
+                    prefixes: prefixes(),
+                    nfa,
+                    cache_size,
+                },
+                Engine::PikeVM => Automaton::PikeVM {
+                    prefixes: prefixes(),
+                    nfa,
+                },
+                Engine::Dfa => {
+                    let precedences = indexes
+                        .iter()
+                        .map(|&i| rules[i].precedence)
+                        .collect::<Vec<_>>();
+                    let dfa = DFA::from_nfa(&nfa, &precedences);
+                    let minimized = dfa.minimize();
+
+                    stats.dfa_states += dfa.state_count();
+                    stats.minimized_dfa_states += minimized.state_count();
+
+                    Automaton::Dfa(Arc::new(minimized))
+                }
+            };
+
+            modes.push(Mode {
+                rules: indexes,
+                automaton,
             });
         }
 
         Ok(Lexicon {
-            ignore_chars: self.ignore_chars,
-            rules,
+            ignore_chars: Arc::new(self.ignore_chars),
+            rules: Arc::new(rules),
+            modes: Arc::new(modes),
+            stats,
+            loaded: false,
         })
     }
 
+    /// Skips `chars` between tokens. Ignored chars are skipped in every mode,
+    /// whatever the rules of the mode are.
     pub fn ignore_chars(mut self, chars: &str) -> Self {
         for ch in chars.chars() {
             self.ignore_chars.insert(ch);
@@ -81,14 +418,131 @@ impl LexiconBuilder {
         self
     }
 
-    pub fn literal(mut self, id: RuleID, literal: &str) -> Self {
-        self.rules.push((id, RuleKind::Literal, literal.into()));
+    /// Builds DFA states on demand while lexing rather than all up front.
+    /// Each `Lexer` caches at most roughly `cache_size` bytes of states, and
+    /// falls back to simulating the NFA if the cache is too small to be useful.
+    ///
+    /// This bounds memory for lexicons whose patterns use large Unicode
+    /// classes, such as `\w` or `[^"]`.
+    pub fn lazy_dfa(mut self, cache_size: usize) -> Self {
+        self.engine = Engine::Lazy(cache_size);
+
+        self
+    }
+
+    /// Builds a minimized DFA up front, whatever engine was chosen.
+    pub(crate) fn dfa(mut self) -> Self {
+        self.engine = Engine::Dfa;
+
+        self
+    }
+
+    /// Matches by simulating the NFA of all rules at once instead of building
+    /// a DFA. Matching is slower, but building is cheap and memory use stays
+    /// proportional to the size of the patterns.
+    pub fn pike_vm(mut self) -> Self {
+        self.engine = Engine::PikeVM;
+
+        self
+    }
+
+    /// Adds the rules that follow to `modes` rather than to `INITIAL`, like
+    /// `<STRING,COMMENT>` before rules in flex.
+    pub fn modes(mut self, modes: &[ModeID]) -> Self {
+        self.modes = modes.to_vec();
+
+        self
+    }
+
+    /// Adds a rule whose matches are skipped like ignored chars, such as
+    /// comments or runs of whitespace. Skipped text still counts when
+    /// tracking positions.
+    pub fn skip(self, pattern: &str) -> Self {
+        self.rule(None, RuleKind::Pattern, pattern, None, false)
+    }
+
+    /// Sets how matching the last rule added changes the mode of the lexer.
+    /// A rule must have been added before.
+    pub fn action(mut self, action: Action) -> Self {
+        debug_assert!(!self.rules.is_empty(), "action set before any rule");
+        if let Some(rule) = self.rules.last_mut() {
+            rule.action = Some(action);
+        }
+
+        self
+    }
+
+    /// Sets the precedence of the last rule added. When several rules match
+    /// the longest text, the one with the highest precedence wins, followed by
+    /// the one added first. Literals start out with a precedence of 1 and
+    /// patterns with 0. A rule must have been added before.
+    pub fn precedence(mut self, precedence: u8) -> Self {
+        debug_assert!(!self.rules.is_empty(), "precedence set before any rule");
+        if let Some(rule) = self.rules.last_mut() {
+            rule.precedence = Some(precedence);
+        }
 
         self
     }
 
-    pub fn pattern(mut self, id: RuleID, pattern: &str) -> Self {
-        self.rules.push((id, RuleKind::Pattern, pattern.into()));
+    pub fn literal(self, id: K, literal: &str) -> Self {
+        self.rule(Some(id), RuleKind::Literal, literal, None, false)
+    }
+
+    /// Adds a literal that matches regardless of case, using Unicode simple
+    /// case folding, so `select` matches `SELECT` and `Select` as well.
+    pub fn literal_ci(self, id: K, literal: &str) -> Self {
+        self.rule(Some(id), RuleKind::Literal, literal, None, true)
+    }
+
+    pub fn pattern(self, id: K, pattern: &str) -> Self {
+        self.rule(Some(id), RuleKind::Pattern, pattern, None, false)
+    }
+
+    /// Adds a pattern that matches regardless of case, as if it began with
+    /// `(?i)`.
+    pub fn pattern_ci(self, id: K, pattern: &str) -> Self {
+        self.rule(Some(id), RuleKind::Pattern, pattern, None, true)
+    }
+
+    /// Adds a rule whose matches end as soon as `pattern` matches, rather
+    /// than at the longest match of `pattern`. For example, `/\*.*\*/` then
+    /// matches a block comment up to the first `*/`.
+    ///
+    /// The rule still competes with other rules by the length of its match.
+    /// Patterns must not contain assertions or match the empty string, which
+    /// would end every match before it begins.
+    pub fn shortest_pattern(self, id: K, pattern: &str) -> Self {
+        self.rule(Some(id), RuleKind::ShortestPattern, pattern, None, false)
+    }
+
+    /// Adds a rule that matches `pattern` only when it is followed by a match
+    /// of `trailing`, like `pattern/trailing` in lex. Only the match of
+    /// `pattern` is consumed, but the whole match counts when choosing the
+    /// longest match, so `[0-9]+` followed by `\.\.` wins over a float
+    /// pattern in `1..2`. `pattern` must not match the empty string.
+    pub fn pattern_with_lookahead(self, id: K, pattern: &str, trailing: &str) -> Self {
+        self.rule(Some(id), RuleKind::Pattern, pattern, Some(trailing), false)
+    }
+
+    fn rule(
+        mut self,
+        id: Option<K>,
+        kind: RuleKind,
+        source: &str,
+        trailing: Option<&str>,
+        case_insensitive: bool,
+    ) -> Self {
+        self.rules.push(RuleDef {
+            id,
+            kind,
+            source: source.into(),
+            trailing: trailing.map(String::from),
+            case_insensitive,
+            modes: self.modes.clone(),
+            action: None,
+            precedence: None,
+        });
 
         self
     }
@@ -1,14 +1,53 @@
+#![allow(clippy::upper_case_acronyms)]
+
+pub mod codegen;
+mod dfa;
 mod lexer;
 mod lexicon;
 mod nfa;
+mod serialize;
+pub mod spec;
 
-pub use crate::lexer::{Error, Lexer, Next, Position};
-pub use crate::lexicon::{Error as LexiconBuilderError, Lexicon, LexiconBuilder};
+pub use crate::lexer::{ByteLexer, Captures, Error, Lexer, Next, Position, Span, Token, Trivia};
+pub use crate::lexicon::{
+    Action, ByteLexicon, Error as LexiconBuilderError, Lexicon, LexiconBuilder, ModeID, Stats,
+    TokenKind, INITIAL,
+};
+pub use crate::nfa::CompileError;
+pub use crate::serialize::Error as LoadError;
 
-#[cfg(test)]
+#[cfg(all(test, feature = "regex-syntax"))]
 mod tests {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
     use crate::*;
 
+    /// Counts the allocations made by each thread.
+    struct CountingAlloc;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAlloc {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            ALLOCATIONS.with(|n| n.set(n.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOC: CountingAlloc = CountingAlloc;
+
+    fn allocations() -> usize {
+        ALLOCATIONS.with(Cell::get)
+    }
+
     fn simple_lexicon() -> Lexicon {
         LexiconBuilder::new()
             .ignore_chars(" ")
@@ -33,12 +72,36 @@ mod tests {
         let lexicon = simple_lexicon();
         let mut lexer = Lexer::new(&lexicon, "a b 1 -     ");
 
-        assert_eq!(lexer.next(), Some(Next::Token(0, "a", Position::new(1, 1))));
-        assert_eq!(lexer.next(), Some(Next::Token(0, "b", Position::new(1, 3))));
-        assert_eq!(lexer.next(), Some(Next::Token(1, "1", Position::new(1, 5))));
         assert_eq!(
             lexer.next(),
-            Some(Next::Error(Error::UnexpectedChar("-"), Position::new(1, 7)))
+            Some(Next::Token(Token {
+                kind: 0,
+                text: "a",
+                span: Span::new(Position::new(0, 1, 1), Position::new(1, 1, 2))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 0,
+                text: "b",
+                span: Span::new(Position::new(2, 1, 3), Position::new(3, 1, 4))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 1,
+                text: "1",
+                span: Span::new(Position::new(4, 1, 5), Position::new(5, 1, 6))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Error(
+                Error::UnexpectedChar("-"),
+                Span::new(Position::new(6, 1, 7), Position::new(7, 1, 8))
+            ))
         );
         assert_eq!(lexer.next(), None);
     }
@@ -58,17 +121,840 @@ mod tests {
 
         assert_eq!(
             lexer.next(),
-            Some(Next::Token(0, "abc", Position::new(1, 4)))
+            Some(Next::Token(Token {
+                kind: 0,
+                text: "abc",
+                span: Span::new(Position::new(3, 1, 4), Position::new(6, 1, 7))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 0,
+                text: "AAaa",
+                span: Span::new(Position::new(7, 1, 8), Position::new(11, 1, 12))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 1,
+                text: "123",
+                span: Span::new(Position::new(12, 1, 13), Position::new(15, 1, 16))
+            }))
+        );
+        assert_eq!(lexer.next(), None);
+    }
+
+    #[test]
+    fn lazy_dfa() {
+        let lexicon = LexiconBuilder::new()
+            .ignore_chars(" ")
+            .pattern(0, r"\w+")
+            .literal(1, "if")
+            .lazy_dfa(1 << 16)
+            .build()
+            .unwrap();
+        let mut lexer = Lexer::new(&lexicon, "if iffy ünïcödé");
+
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 1,
+                text: "if",
+                span: Span::new(Position::new(0, 1, 1), Position::new(2, 1, 3))
+            }))
         );
         assert_eq!(
             lexer.next(),
-            Some(Next::Token(0, "AAaa", Position::new(1, 8)))
+            Some(Next::Token(Token {
+                kind: 0,
+                text: "iffy",
+                span: Span::new(Position::new(3, 1, 4), Position::new(7, 1, 8))
+            }))
         );
         assert_eq!(
             lexer.next(),
-            Some(Next::Token(1, "123", Position::new(1, 13)))
+            Some(Next::Token(Token {
+                kind: 0,
+                text: "ünïcödé",
+                span: Span::new(Position::new(8, 1, 9), Position::new(19, 1, 16))
+            }))
         );
         assert_eq!(lexer.next(), None);
+        assert_eq!(lexicon.stats().dfa_states, 0);
+    }
+
+    #[test]
+    fn pike_vm() {
+        let lexicon = LexiconBuilder::new()
+            .ignore_chars(" ")
+            .pattern(0, r"\w+")
+            .literal(1, "if")
+            .pike_vm()
+            .build()
+            .unwrap();
+        let mut lexer = Lexer::new(&lexicon, "if iffy ünïcödé");
+
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 1,
+                text: "if",
+                span: Span::new(Position::new(0, 1, 1), Position::new(2, 1, 3))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 0,
+                text: "iffy",
+                span: Span::new(Position::new(3, 1, 4), Position::new(7, 1, 8))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 0,
+                text: "ünïcödé",
+                span: Span::new(Position::new(8, 1, 9), Position::new(19, 1, 16))
+            }))
+        );
+        assert_eq!(lexer.next(), None);
+        assert_eq!(lexicon.stats().dfa_states, 0);
+    }
+
+    #[test]
+    fn anchors() {
+        let lexicon = LexiconBuilder::new()
+            .ignore_chars(" \n")
+            .pattern(0, r"^#[a-z]+")
+            .pattern(1, r"#")
+            .pattern(2, r"[a-z]+$")
+            .pattern(3, r"[a-z]+")
+            .build()
+            .unwrap();
+        let mut lexer = Lexer::new(&lexicon, "#define x y\n a #b\n#c");
+
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 0,
+                text: "#define",
+                span: Span::new(Position::new(0, 1, 1), Position::new(7, 1, 8))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 3,
+                text: "x",
+                span: Span::new(Position::new(8, 1, 9), Position::new(9, 1, 10))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 2,
+                text: "y",
+                span: Span::new(Position::new(10, 1, 11), Position::new(11, 1, 12))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 3,
+                text: "a",
+                span: Span::new(Position::new(13, 2, 2), Position::new(14, 2, 3))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 1,
+                text: "#",
+                span: Span::new(Position::new(15, 2, 4), Position::new(16, 2, 5))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 2,
+                text: "b",
+                span: Span::new(Position::new(16, 2, 5), Position::new(17, 2, 6))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 0,
+                text: "#c",
+                span: Span::new(Position::new(18, 3, 1), Position::new(20, 3, 3))
+            }))
+        );
+        assert_eq!(lexer.next(), None);
+    }
+
+    #[test]
+    fn word_boundaries() {
+        let engines: [fn(LexiconBuilder) -> LexiconBuilder; 3] =
+            [|b| b, |b| b.lazy_dfa(1 << 16), |b| b.pike_vm()];
+
+        for engine in engines.iter() {
+            let builder = LexiconBuilder::new()
+                .ignore_chars(" ")
+                .pattern(0, r"in\b")
+                .pattern(1, r"[a-z]+")
+                .pattern(2, r"[0-9]+");
+            let lexicon = engine(builder).build().unwrap();
+            let mut lexer = Lexer::new(&lexicon, "in1 in inx");
+
+            assert_eq!(
+                lexer.next(),
+                Some(Next::Token(Token {
+                    kind: 1,
+                    text: "in",
+                    span: Span::new(Position::new(0, 1, 1), Position::new(2, 1, 3))
+                }))
+            );
+            assert_eq!(
+                lexer.next(),
+                Some(Next::Token(Token {
+                    kind: 2,
+                    text: "1",
+                    span: Span::new(Position::new(2, 1, 3), Position::new(3, 1, 4))
+                }))
+            );
+            assert_eq!(
+                lexer.next(),
+                Some(Next::Token(Token {
+                    kind: 0,
+                    text: "in",
+                    span: Span::new(Position::new(4, 1, 5), Position::new(6, 1, 7))
+                }))
+            );
+            assert_eq!(
+                lexer.next(),
+                Some(Next::Token(Token {
+                    kind: 1,
+                    text: "inx",
+                    span: Span::new(Position::new(7, 1, 8), Position::new(10, 1, 11))
+                }))
+            );
+            assert_eq!(lexer.next(), None);
+        }
+    }
+
+    #[test]
+    fn trailing_context() {
+        let engines: [fn(LexiconBuilder) -> LexiconBuilder; 3] =
+            [|b| b, |b| b.lazy_dfa(1 << 16), |b| b.pike_vm()];
+
+        for engine in engines.iter() {
+            let builder = LexiconBuilder::new()
+                .ignore_chars(" ")
+                .pattern_with_lookahead(0, r"[0-9]+", r"\.\.")
+                .pattern(1, r"[0-9]+")
+                .pattern(2, r"[0-9]+\.[0-9]*")
+                .literal(3, "..");
+            let lexicon = engine(builder).build().unwrap();
+            let mut lexer = Lexer::new(&lexicon, "1..2 1.5");
+
+            assert_eq!(
+                lexer.next(),
+                Some(Next::Token(Token {
+                    kind: 0,
+                    text: "1",
+                    span: Span::new(Position::new(0, 1, 1), Position::new(1, 1, 2))
+                }))
+            );
+            assert_eq!(
+                lexer.next(),
+                Some(Next::Token(Token {
+                    kind: 3,
+                    text: "..",
+                    span: Span::new(Position::new(1, 1, 2), Position::new(3, 1, 4))
+                }))
+            );
+            assert_eq!(
+                lexer.next(),
+                Some(Next::Token(Token {
+                    kind: 1,
+                    text: "2",
+                    span: Span::new(Position::new(3, 1, 4), Position::new(4, 1, 5))
+                }))
+            );
+            assert_eq!(
+                lexer.next(),
+                Some(Next::Token(Token {
+                    kind: 2,
+                    text: "1.5",
+                    span: Span::new(Position::new(5, 1, 6), Position::new(8, 1, 9))
+                }))
+            );
+            assert_eq!(lexer.next(), None);
+        }
+
+        let result = LexiconBuilder::new()
+            .pattern_with_lookahead(0, r"[0-9]*", r"\.\.")
+            .build();
+        assert!(matches!(
+            result,
+            Err(LexiconBuilderError::EmptyTrailingContextHead(0))
+        ));
+    }
+
+    #[test]
+    fn shortest_patterns() {
+        let engines: [fn(LexiconBuilder) -> LexiconBuilder; 3] =
+            [|b| b, |b| b.lazy_dfa(1 << 16), |b| b.pike_vm()];
+
+        for engine in engines.iter() {
+            let builder = LexiconBuilder::new()
+                .ignore_chars(" ")
+                .shortest_pattern(0, r"/\*(.|\n)*\*/")
+                .pattern(1, r"[a-z]+");
+            let lexicon = engine(builder).build().unwrap();
+            let mut lexer = Lexer::new(&lexicon, "/* a */ b /* c\n*/");
+
+            assert_eq!(
+                lexer.next(),
+                Some(Next::Token(Token {
+                    kind: 0,
+                    text: "/* a */",
+                    span: Span::new(Position::new(0, 1, 1), Position::new(7, 1, 8))
+                }))
+            );
+            assert_eq!(
+                lexer.next(),
+                Some(Next::Token(Token {
+                    kind: 1,
+                    text: "b",
+                    span: Span::new(Position::new(8, 1, 9), Position::new(9, 1, 10))
+                }))
+            );
+            assert_eq!(
+                lexer.next(),
+                Some(Next::Token(Token {
+                    kind: 0,
+                    text: "/* c\n*/",
+                    span: Span::new(Position::new(10, 1, 11), Position::new(17, 2, 3))
+                }))
+            );
+            assert_eq!(lexer.next(), None);
+        }
+
+        let result = LexiconBuilder::new().shortest_pattern(0, r"a*").build();
+        assert!(matches!(
+            result,
+            Err(LexiconBuilderError::EmptyShortestPattern(0))
+        ));
+    }
+
+    #[test]
+    fn captures() {
+        let lexicon = LexiconBuilder::new()
+            .ignore_chars(" ")
+            .pattern(0, r#""([^"]*)""#)
+            .pattern(
+                1,
+                r"(?P<mantissa>[0-9]+(?:\.[0-9]+)?)(?:e(?P<exponent>[0-9]+))?",
+            )
+            .literal(2, "=")
+            .build()
+            .unwrap();
+        let mut lexer = Lexer::new(&lexicon, r#""abc" 1.5e3 = 2"#);
+
+        assert_eq!(lexer.captures(), None);
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 0,
+                text: r#""abc""#,
+                span: Span::new(Position::new(0, 1, 1), Position::new(5, 1, 6))
+            }))
+        );
+        assert_eq!(lexer.captures().unwrap().get(1), Some("abc"));
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 1,
+                text: "1.5e3",
+                span: Span::new(Position::new(6, 1, 7), Position::new(11, 1, 12))
+            }))
+        );
+        let captures = lexer.captures().unwrap();
+        assert_eq!(captures.get(0), Some("1.5e3"));
+        assert_eq!(captures.name("mantissa"), Some("1.5"));
+        assert_eq!(captures.name("exponent"), Some("3"));
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 2,
+                text: "=",
+                span: Span::new(Position::new(12, 1, 13), Position::new(13, 1, 14))
+            }))
+        );
+        assert_eq!(lexer.captures().unwrap().get(1), None);
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 1,
+                text: "2",
+                span: Span::new(Position::new(14, 1, 15), Position::new(15, 1, 16))
+            }))
+        );
+        assert_eq!(lexer.captures().unwrap().name("exponent"), None);
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let lexicon = LexiconBuilder::new()
+            .ignore_chars(" ")
+            .literal_ci(0, "select")
+            .literal_ci(1, "straße")
+            .pattern_ci(2, r"x[a-z]*")
+            .pattern(3, r"[a-zA-Z]+")
+            .build()
+            .unwrap();
+        let mut lexer = Lexer::new(&lexicon, "SELECT Select STRASSE STRAẞE XyZ Y");
+
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 0,
+                text: "SELECT",
+                span: Span::new(Position::new(0, 1, 1), Position::new(6, 1, 7))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 0,
+                text: "Select",
+                span: Span::new(Position::new(7, 1, 8), Position::new(13, 1, 14))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 3,
+                text: "STRASSE",
+                span: Span::new(Position::new(14, 1, 15), Position::new(21, 1, 22))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 1,
+                text: "STRAẞE",
+                span: Span::new(Position::new(22, 1, 23), Position::new(30, 1, 29))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 2,
+                text: "XyZ",
+                span: Span::new(Position::new(31, 1, 30), Position::new(34, 1, 33))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 3,
+                text: "Y",
+                span: Span::new(Position::new(35, 1, 34), Position::new(36, 1, 35))
+            }))
+        );
+        assert_eq!(lexer.next(), None);
+
+        let lexicon = LexiconBuilder::new()
+            .literal_ci(0, "ék")
+            .build_bytes()
+            .unwrap();
+        let mut lexer = ByteLexer::new(&lexicon, "ÉK\u{e9}\u{212A}".as_bytes());
+
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 0,
+                text: "ÉK".as_bytes(),
+                span: Span::new(Position::new(0, 1, 1), Position::new(3, 1, 3))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 0,
+                text: "é\u{212A}".as_bytes(),
+                span: Span::new(Position::new(3, 1, 3), Position::new(8, 1, 5))
+            }))
+        );
+        assert_eq!(lexer.next(), None);
+    }
+
+    #[test]
+    fn modes() {
+        const STRING: ModeID = 1;
+
+        let lexicon = LexiconBuilder::new()
+            .ignore_chars(" ")
+            .pattern(0, r"[a-z]+")
+            .literal(1, "\"")
+            .action(Action::Push(STRING))
+            .literal(2, "}")
+            .action(Action::Pop)
+            .modes(&[STRING])
+            .pattern(3, r#"[^"$]+"#)
+            .literal(4, "${")
+            .action(Action::Push(INITIAL))
+            .literal(1, "\"")
+            .action(Action::Pop)
+            .build()
+            .unwrap();
+        let mut lexer = Lexer::new(&lexicon, r#"a "x${b}y" c"#);
+
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 0,
+                text: "a",
+                span: Span::new(Position::new(0, 1, 1), Position::new(1, 1, 2))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 1,
+                text: "\"",
+                span: Span::new(Position::new(2, 1, 3), Position::new(3, 1, 4))
+            }))
+        );
+        assert_eq!(lexer.mode(), STRING);
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 3,
+                text: "x",
+                span: Span::new(Position::new(3, 1, 4), Position::new(4, 1, 5))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 4,
+                text: "${",
+                span: Span::new(Position::new(4, 1, 5), Position::new(6, 1, 7))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 0,
+                text: "b",
+                span: Span::new(Position::new(6, 1, 7), Position::new(7, 1, 8))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 2,
+                text: "}",
+                span: Span::new(Position::new(7, 1, 8), Position::new(8, 1, 9))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 3,
+                text: "y",
+                span: Span::new(Position::new(8, 1, 9), Position::new(9, 1, 10))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 1,
+                text: "\"",
+                span: Span::new(Position::new(9, 1, 10), Position::new(10, 1, 11))
+            }))
+        );
+        assert_eq!(lexer.mode(), INITIAL);
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 0,
+                text: "c",
+                span: Span::new(Position::new(11, 1, 12), Position::new(12, 1, 13))
+            }))
+        );
+        assert_eq!(lexer.next(), None);
+    }
+
+    #[test]
+    fn skip() {
+        let lexicon = LexiconBuilder::new()
+            .skip(r"[ \t\n]+")
+            .skip(r"//[^\n]*")
+            .skip(r"/\*([^*]|\*+[^*/])*\*+/")
+            .pattern(0, r"[a-z]+")
+            .literal(1, "/")
+            .build()
+            .unwrap();
+        let mut lexer = Lexer::new(&lexicon, "a // b\n\t/* c\n */ d / e");
+
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 0,
+                text: "a",
+                span: Span::new(Position::new(0, 1, 1), Position::new(1, 1, 2))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 0,
+                text: "d",
+                span: Span::new(Position::new(17, 3, 5), Position::new(18, 3, 6))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 1,
+                text: "/",
+                span: Span::new(Position::new(19, 3, 7), Position::new(20, 3, 8))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 0,
+                text: "e",
+                span: Span::new(Position::new(21, 3, 9), Position::new(22, 3, 10))
+            }))
+        );
+        assert_eq!(lexer.next(), None);
+    }
+
+    #[test]
+    fn trivia() {
+        let lexicon = LexiconBuilder::new()
+            .ignore_chars(" \n")
+            .skip(r"//[^\n]*")
+            .pattern(0, r"[a-z]+")
+            .build()
+            .unwrap();
+        let input = "  a b // c\n\n d ! // e\n  ";
+        let mut lexer = Lexer::new(&lexicon, input).preserve_trivia();
+
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 0,
+                text: "a",
+                span: Span::new(Position::new(2, 1, 3), Position::new(3, 1, 4))
+            }))
+        );
+        assert_eq!(
+            lexer.trivia(),
+            Some(Trivia {
+                leading: 0..2,
+                trailing: 3..4
+            })
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 0,
+                text: "b",
+                span: Span::new(Position::new(4, 1, 5), Position::new(5, 1, 6))
+            }))
+        );
+        assert_eq!(
+            lexer.trivia(),
+            Some(Trivia {
+                leading: 4..4,
+                trailing: 5..11
+            })
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 0,
+                text: "d",
+                span: Span::new(Position::new(13, 3, 2), Position::new(14, 3, 3))
+            }))
+        );
+        assert_eq!(
+            lexer.trivia(),
+            Some(Trivia {
+                leading: 11..13,
+                trailing: 14..15
+            })
+        );
+
+        let mut lexer = Lexer::new(&lexicon, input).preserve_trivia();
+        let mut output = String::new();
+        loop {
+            let text = match lexer.next() {
+                Some(Next::Token(Token { text, .. })) => text,
+                Some(Next::Error(Error::UnexpectedChar(text), _)) => text,
+                None => break,
+            };
+            let trivia = lexer.trivia().unwrap();
+            output.push_str(&input[trivia.leading]);
+            output.push_str(text);
+            output.push_str(&input[trivia.trailing]);
+        }
+        output.push_str(&input[lexer.trivia().unwrap().leading]);
+
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn reset_with() {
+        let builder = || {
+            LexiconBuilder::new()
+                .ignore_chars(" ")
+                .pattern(0, r"[a-z]+\b")
+                .literal(1, "if")
+        };
+        let lexicons = [
+            builder().build().unwrap(),
+            builder().lazy_dfa(1 << 16).build().unwrap(),
+            builder().pike_vm().build().unwrap(),
+        ];
+
+        for lexicon in lexicons.iter() {
+            let before = allocations();
+            let mut lexer = Lexer::new(lexicon, "ab if cd");
+            assert_eq!(allocations(), before);
+            assert_eq!(lexer.by_ref().count(), 3);
+
+            // The states built while lexing are kept, so lexing the same
+            // tokens again does not allocate
+            let before = allocations();
+            lexer.reset_with("cd ab");
+            assert_eq!(
+                lexer.next(),
+                Some(Next::Token(Token {
+                    kind: 0,
+                    text: "cd",
+                    span: Span::new(Position::new(0, 1, 1), Position::new(2, 1, 3))
+                }))
+            );
+            assert_eq!(lexer.by_ref().count(), 1);
+            lexer.reset_with("ab if cd");
+            assert_eq!(lexer.by_ref().count(), 3);
+            assert_eq!(allocations(), before);
+        }
+    }
+
+    #[test]
+    fn send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Lexicon>();
+        assert_send_sync::<ByteLexicon>();
+        assert_send_sync::<Lexer>();
+        assert_send_sync::<ByteLexer>();
+
+        let lexicon = std::sync::Arc::new(simple_lexicon());
+        let counts = ["a b", "1 2 3", ""]
+            .iter()
+            .map(|input| {
+                let lexicon = lexicon.clone();
+                std::thread::spawn(move || Lexer::new(&lexicon, input).count())
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(counts, [2, 3, 0]);
+    }
+
+    #[test]
+    fn iterator() {
+        let lexicon = simple_lexicon();
+        let input = String::from("abc 12 - if x");
+
+        let words = Lexer::new(&lexicon, &input)
+            .filter_map(|next| match next {
+                Next::Token(token) if token.kind != 1 => Some(token.text),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(words, ["abc", "if", "x"]);
+
+        let mut lexer = Lexer::new(&lexicon, &input).peekable();
+        let mut errors = 0;
+        while let Some(next) = lexer.next() {
+            if let Next::Error(..) = next {
+                errors += 1;
+                assert!(lexer.peek().is_some());
+            }
+        }
+        assert_eq!(errors, 1);
+
+        let lexicon = LexiconBuilder::new()
+            .pattern(0, "[a-z]+")
+            .build_bytes()
+            .unwrap();
+        let kinds = ByteLexer::new(&lexicon, b"ab\xFFc")
+            .map(|next| match next {
+                Next::Token(token) => Some(token.kind),
+                Next::Error(..) => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(kinds, [Some(0), None, Some(0)]);
+    }
+
+    #[test]
+    fn kinds() {
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        enum Kind {
+            Ident,
+            Number,
+            If,
+        }
+
+        let lexicon = LexiconBuilder::new()
+            .ignore_chars(" ")
+            .pattern(Kind::Ident, r"[a-z]+")
+            .pattern(Kind::Number, r"[0-9]+")
+            .literal(Kind::If, "if")
+            .build()
+            .unwrap();
+        let kinds = Lexer::new(&lexicon, "if x 1")
+            .map(|next| match next {
+                Next::Token(token) => token.kind,
+                Next::Error(..) => panic!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(kinds, [Kind::If, Kind::Ident, Kind::Number]);
+
+        let error = LexiconBuilder::new().literal(Kind::If, "").build();
+        match error {
+            Err(LexiconBuilderError::EmptyLiteral(Kind::If)) => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn stats() {
+        let lexicon = LexiconBuilder::new()
+            .pattern(0, "[a-z]+|[A-Z]+")
+            .pattern(1, "0x[0-9]+|0X[0-9]+")
+            .literal(2, "if")
+            .build()
+            .unwrap();
+        let stats = lexicon.stats();
+
+        assert_eq!(stats.nfa_states, 20);
+        assert_eq!(stats.dfa_states, 10);
+        assert_eq!(stats.minimized_dfa_states, 8);
     }
 
     #[test]
@@ -78,19 +964,151 @@ mod tests {
 
         assert_eq!(
             lexer.next(),
-            Some(Next::Token(0, "abc", Position::new(1, 4)))
+            Some(Next::Token(Token {
+                kind: 0,
+                text: "abc",
+                span: Span::new(Position::new(3, 1, 4), Position::new(6, 1, 7))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 2,
+                text: "if",
+                span: Span::new(Position::new(7, 1, 8), Position::new(9, 1, 10))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 0,
+                text: "iffy",
+                span: Span::new(Position::new(10, 1, 11), Position::new(14, 1, 15))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 1,
+                text: "123",
+                span: Span::new(Position::new(15, 1, 16), Position::new(18, 1, 19))
+            }))
+        );
+        assert_eq!(lexer.next(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid rules for token kind: EmptyLiteral(A)")]
+    fn token_kind_errors() {
+        #[derive(Clone, Copy, Debug)]
+        enum Kind {
+            A,
+        }
+
+        impl TokenKind for Kind {
+            fn builder() -> LexiconBuilder<Self> {
+                LexiconBuilder::new().literal(Kind::A, "")
+            }
+        }
+
+        Kind::lexicon();
+    }
+
+    #[test]
+    fn precedence() {
+        let builder = || {
+            LexiconBuilder::new()
+                .ignore_chars(" ")
+                .literal(0, "if")
+                .pattern(1, "[a-z]+")
+                .precedence(2)
+                .pattern(2, "[0-9]+")
+                .literal(3, "12")
+                .precedence(0)
+        };
+        let lexicons = [
+            builder().build().unwrap(),
+            builder().lazy_dfa(1 << 16).build().unwrap(),
+            builder().pike_vm().build().unwrap(),
+        ];
+
+        for lexicon in lexicons.iter() {
+            let kinds = Lexer::new(lexicon, "if 12 iffy")
+                .map(|next| match next {
+                    Next::Token(token) => token.kind,
+                    Next::Error(..) => usize::MAX,
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(kinds, vec![1, 2, 1]);
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "precedence set before any rule")]
+    fn precedence_before_rule() {
+        let _ = LexiconBuilder::<usize>::new()
+            .ignore_chars(" ")
+            .precedence(2);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "action set before any rule")]
+    fn action_before_rule() {
+        let _ = LexiconBuilder::<usize>::new()
+            .modes(&[1])
+            .action(Action::Pop);
+    }
+
+    #[test]
+    fn bytes() {
+        let lexicon = LexiconBuilder::new()
+            .ignore_chars(" ")
+            .pattern(0, r"\w+")
+            .pattern(1, r"(?-u:\xFF\xD8)")
+            .literal(2, "é")
+            .build_bytes()
+            .unwrap();
+        let mut lexer = ByteLexer::new(&lexicon, b"caf\xC3\xA9 \xFF\xD8\xC3\xA9 \xFF!");
+
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 0,
+                text: &b"caf\xC3\xA9"[..],
+                span: Span::new(Position::new(0, 1, 1), Position::new(5, 1, 5))
+            }))
+        );
+        assert_eq!(
+            lexer.next(),
+            Some(Next::Token(Token {
+                kind: 1,
+                text: &b"\xFF\xD8"[..],
+                span: Span::new(Position::new(6, 1, 6), Position::new(8, 1, 8))
+            }))
         );
         assert_eq!(
             lexer.next(),
-            Some(Next::Token(2, "if", Position::new(1, 8)))
+            Some(Next::Token(Token {
+                kind: 2,
+                text: &b"\xC3\xA9"[..],
+                span: Span::new(Position::new(8, 1, 8), Position::new(10, 1, 9))
+            }))
         );
         assert_eq!(
             lexer.next(),
-            Some(Next::Token(0, "iffy", Position::new(1, 11)))
+            Some(Next::Error(
+                Error::UnexpectedChar(&b"\xFF"[..]),
+                Span::new(Position::new(11, 1, 10), Position::new(12, 1, 11))
+            ))
         );
         assert_eq!(
             lexer.next(),
-            Some(Next::Token(1, "123", Position::new(1, 16)))
+            Some(Next::Error(
+                Error::UnexpectedChar(&b"!"[..]),
+                Span::new(Position::new(12, 1, 11), Position::new(13, 1, 12))
+            ))
         );
         assert_eq!(lexer.next(), None);
     }
@@ -0,0 +1,206 @@
+use std::char;
+use std::collections::BTreeSet;
+
+use hashbrown::HashMap;
+
+use super::CharRange;
+
+/// A partition of all chars into equivalence classes, such that every char in
+/// a class belongs to exactly the same char sets. Automata built over the
+/// alphabet can then index their transitions by class instead of by char.
+///
+/// Class 0 holds the chars that are in none of the sets.
+#[derive(Clone, Debug)]
+pub struct Alphabet {
+    pub(crate) ascii: [u32; 128],
+    /// The classes of non-ASCII chars outside of class 0, sorted by range.
+    pub(crate) ranges: Vec<(char, char, u32)>,
+    pub(crate) classes: usize,
+}
+
+impl Alphabet {
+    pub fn new<'a>(sets: impl Iterator<Item = &'a [CharRange]>) -> Alphabet {
+        let mut events = vec![];
+        for (i, set) in sets.enumerate() {
+            for (lo, hi) in set.iter() {
+                events.push((*lo as u32, i, true));
+                events.push((*hi as u32 + 1, i, false));
+            }
+        }
+        events.sort_unstable();
+
+        let mut alphabet = Alphabet {
+            ascii: [0; 128],
+            ranges: vec![],
+            classes: 1,
+        };
+
+        let mut classes = HashMap::new();
+        classes.insert(vec![], 0);
+
+        let mut active = BTreeSet::new();
+        let mut i = 0;
+        while i < events.len() {
+            let lo = events[i].0;
+            while i < events.len() && events[i].0 == lo {
+                let (_, set, start) = events[i];
+                if start {
+                    active.insert(set);
+                } else {
+                    active.remove(&set);
+                }
+                i += 1;
+            }
+
+            let hi = match events.get(i) {
+                Some((next, _, _)) => next - 1,
+                None => break,
+            };
+
+            // Skip over surrogates, which are not valid chars
+            let lo = if is_surrogate(lo) { 0xE000 } else { lo };
+            let hi = if is_surrogate(hi) { 0xD7FF } else { hi };
+            if lo > hi {
+                continue;
+            }
+
+            let signature = active.iter().copied().collect::<Vec<_>>();
+            let next_class = classes.len() as u32;
+            let class = *classes.entry(signature).or_insert(next_class);
+            alphabet.insert(lo, hi, class);
+        }
+
+        alphabet.classes = classes.len();
+        alphabet
+    }
+
+    fn insert(&mut self, lo: u32, hi: u32, class: u32) {
+        for c in lo..=hi.min(127) {
+            self.ascii[c as usize] = class;
+        }
+
+        let lo = lo.max(128);
+        if lo <= hi && class != 0 {
+            let lo = char::from_u32(lo).unwrap();
+            let hi = char::from_u32(hi).unwrap();
+            self.ranges.push((lo, hi, class));
+        }
+    }
+
+    #[inline]
+    pub fn class_of(&self, c: char) -> usize {
+        if (c as u32) < 128 {
+            return self.ascii[c as usize] as usize;
+        }
+
+        self.ranges
+            .binary_search_by(|(lo, hi, _)| {
+                if *hi < c {
+                    std::cmp::Ordering::Less
+                } else if *lo > c {
+                    std::cmp::Ordering::Greater
+                } else {
+                    std::cmp::Ordering::Equal
+                }
+            })
+            .map(|i| self.ranges[i].2 as usize)
+            .unwrap_or(0)
+    }
+
+    /// Returns the classes of the chars within `range`, which must be covered
+    /// by the sets the alphabet was built from.
+    pub fn classes(&self, (lo, hi): CharRange) -> Vec<usize> {
+        let mut classes = vec![];
+
+        for c in (lo as u32)..=(hi as u32).min(127) {
+            classes.push(self.ascii[c as usize] as usize);
+        }
+
+        if hi as u32 >= 128 {
+            let lo = lo.max('\u{80}');
+            let first = self.ranges.partition_point(|(_, r_hi, _)| *r_hi < lo);
+            classes.extend(
+                self.ranges[first..]
+                    .iter()
+                    .take_while(|(r_lo, _, _)| *r_lo <= hi)
+                    .map(|(_, _, class)| *class as usize),
+            );
+        }
+
+        classes.sort_unstable();
+        classes.dedup();
+        classes
+    }
+
+    /// Returns the ranges of chars in `class`, which must not be class 0.
+    pub fn ranges(&self, class: usize) -> Vec<CharRange> {
+        let mut ranges: Vec<CharRange> = vec![];
+
+        for c in (0..128u8).filter(|&c| self.ascii[c as usize] as usize == class) {
+            let c = char::from(c);
+            match ranges.last_mut() {
+                Some((_, hi)) if *hi as u32 + 1 == c as u32 => *hi = c,
+                _ => ranges.push((c, c)),
+            }
+        }
+
+        ranges.extend(
+            self.ranges
+                .iter()
+                .filter(|(_, _, c)| *c as usize == class)
+                .map(|(lo, hi, _)| (*lo, *hi)),
+        );
+        ranges
+    }
+
+    /// Returns the number of classes.
+    pub fn len(&self) -> usize {
+        self.classes
+    }
+}
+
+fn is_surrogate(c: u32) -> bool {
+    (0xD800..=0xDFFF).contains(&c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Alphabet;
+
+    #[test]
+    fn classes() {
+        let letters = [('a', 'z')];
+        let vowels = [('a', 'a'), ('e', 'e'), ('i', 'i'), ('o', 'o'), ('u', 'u')];
+        let alphabet = Alphabet::new(vec![&letters[..], &vowels[..]].into_iter());
+
+        assert_eq!(alphabet.len(), 3);
+        assert_eq!(alphabet.class_of('0'), 0);
+        assert_eq!(alphabet.class_of('a'), alphabet.class_of('u'));
+        assert_eq!(alphabet.class_of('b'), alphabet.class_of('z'));
+        assert_ne!(alphabet.class_of('a'), alphabet.class_of('b'));
+        assert_ne!(alphabet.class_of('b'), 0);
+    }
+
+    #[test]
+    fn non_ascii() {
+        let negated = [
+            ('\u{0}', '`'),
+            ('b', '\u{D7FF}'),
+            ('\u{E000}', '\u{10FFFF}'),
+        ];
+        let greek = [('α', 'ω')];
+        let alphabet = Alphabet::new(vec![&negated[..], &greek[..]].into_iter());
+
+        assert_eq!(alphabet.len(), 3);
+        assert_eq!(alphabet.class_of('a'), 0);
+        assert_eq!(alphabet.class_of('b'), alphabet.class_of('\u{10FFFF}'));
+        assert_eq!(alphabet.class_of('b'), alphabet.class_of('é'));
+        assert_ne!(alphabet.class_of('b'), alphabet.class_of('β'));
+        assert_eq!(alphabet.classes(('α', 'ω')), vec![alphabet.class_of('β')]);
+        assert_eq!(alphabet.ranges(alphabet.class_of('β')), vec![('α', 'ω')]);
+        assert_eq!(
+            alphabet.classes(('\u{0}', '`')),
+            vec![alphabet.class_of('b')]
+        );
+    }
+}
@@ -1,29 +1,113 @@
-use std::char;
+use fixedbitset::FixedBitSet;
 
-use hashbrown::HashSet;
+use super::{Alphabet, CharRange, NFA};
 
-use super::NFA;
+/// Maps the first char of the input to the rules that can match it.
+#[derive(Clone, Debug)]
+pub struct Prefixes {
+    alphabet: Alphabet,
+    rules: Vec<FixedBitSet>,
+}
 
 impl NFA {
-    pub fn starting_chars(&self) -> HashSet<char> {
+    /// Returns the sorted, non-overlapping ranges of chars that can begin a
+    /// match. Assertions are assumed to hold.
+    pub fn starting_ranges(&self) -> Vec<CharRange> {
         let mut state = self.execution_state();
-        self.initialize_states(&mut state.current);
-
-        let mut chars = HashSet::new();
-
-        for i in state.current.ones() {
-            let s = &self.states[i];
-            for t in s.transitions.iter() {
-                for (low, high) in t.ranges.iter() {
-                    for i in (*low as u32)..=(*high as u32) {
-                        if let Some(c) = char::from_u32(i) {
-                            chars.insert(c);
-                        }
-                    }
+        state.current.clear();
+        self.add_states(&mut state.current, 0, &|_| Some(true));
+
+        let mut ranges = state
+            .current
+            .ones()
+            .flat_map(|i| self.states[i].transitions.iter())
+            .flat_map(|t| t.ranges.iter().copied())
+            .collect::<Vec<_>>();
+        ranges.sort_unstable();
+
+        let mut merged: Vec<CharRange> = vec![];
+        for (lo, hi) in ranges {
+            match merged.last_mut() {
+                Some((_, prev_hi)) if (*prev_hi as u32) + 1 >= lo as u32 => {
+                    *prev_hi = (*prev_hi).max(hi);
+                }
+                _ => merged.push((lo, hi)),
+            }
+        }
+
+        merged
+    }
+}
+
+impl Prefixes {
+    /// Builds the dispatch table from the starting ranges of each rule.
+    pub fn new(starting_ranges: &[Vec<CharRange>]) -> Prefixes {
+        let alphabet = Alphabet::new(starting_ranges.iter().map(|r| &r[..]));
+
+        let mut rules = vec![FixedBitSet::with_capacity(starting_ranges.len()); alphabet.len()];
+        for (rule, ranges) in starting_ranges.iter().enumerate() {
+            for range in ranges.iter() {
+                for class in alphabet.classes(*range) {
+                    rules[class].insert(rule);
                 }
             }
         }
 
-        chars
+        Prefixes { alphabet, rules }
+    }
+
+    /// Returns the starting ranges of each rule, as passed to `new`, with
+    /// adjacent ranges possibly split or merged.
+    pub fn starting_ranges(&self) -> Vec<Vec<CharRange>> {
+        let mut ranges = vec![vec![]; self.rules.first().map_or(0, |r| r.len())];
+        for (class, rules) in self.rules.iter().enumerate().skip(1) {
+            for rule in rules.ones() {
+                ranges[rule].extend(self.alphabet.ranges(class));
+            }
+        }
+
+        ranges
+    }
+
+    #[inline]
+    pub fn rules_for(&self, c: char) -> &FixedBitSet {
+        &self.rules[self.alphabet.class_of(c)]
+    }
+}
+
+#[cfg(all(test, feature = "regex-syntax"))]
+mod tests {
+    use super::Prefixes;
+    use crate::nfa::NFA;
+
+    #[test]
+    fn starting_ranges() {
+        let nfa = NFA::from_regex("[a-c]x|[b-f]y|z|[^a-z]|`").unwrap();
+
+        assert_eq!(
+            nfa.starting_ranges(),
+            vec![('\u{0}', 'f'), ('z', '\u{10FFFF}')]
+        );
+    }
+
+    #[test]
+    fn prefixes() {
+        let ranges = ["[a-z]+", "[^a]", "if", "é"]
+            .iter()
+            .map(|p| NFA::from_regex(p).unwrap().starting_ranges())
+            .collect::<Vec<_>>();
+        let prefixes = Prefixes::new(&ranges);
+
+        let rules_for = |c| prefixes.rules_for(c).ones().collect::<Vec<_>>();
+        assert_eq!(rules_for('a'), vec![0]);
+        assert_eq!(rules_for('i'), vec![0, 1, 2]);
+        assert_eq!(rules_for('0'), vec![1]);
+        assert_eq!(rules_for('é'), vec![1, 3]);
+        assert_eq!(rules_for('\u{10FFFF}'), vec![1]);
+
+        let rebuilt = Prefixes::new(&prefixes.starting_ranges());
+        for c in ['a', 'i', '0', 'é', '\u{10FFFF}'].iter() {
+            assert_eq!(rebuilt.rules_for(*c), prefixes.rules_for(*c));
+        }
     }
 }
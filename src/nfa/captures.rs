@@ -0,0 +1,152 @@
+use fixedbitset::FixedBitSet;
+
+use super::{Context, StateID, NFA};
+
+/// The offsets recorded in each capture slot. Group `i` starts at slot
+/// `2 * (i - 1)` and ends at the slot after it.
+type Slots = Vec<Option<usize>>;
+
+impl NFA {
+    /// Returns the number of capture groups, not counting the whole match.
+    pub fn capture_count(&self) -> usize {
+        self.capture_names.len()
+    }
+
+    /// Returns the offsets of each capture group in a match of the whole of
+    /// `symbols`, where `prev` and `next` are the chars around the match.
+    ///
+    /// When the match can be split in several ways, greedy repetitions and
+    /// the leftmost alternatives are preferred, as in a backtracking regex
+    /// engine.
+    pub fn captures(
+        &self,
+        symbols: impl Iterator<Item = (usize, char)>,
+        prev: Option<char>,
+        next: Option<char>,
+    ) -> Option<Vec<Option<(usize, usize)>>> {
+        let mut symbols = symbols.peekable();
+        let after = self.context_of(next);
+        let context_after =
+            |c: Option<&(usize, char)>| c.map_or(after, |&(_, c)| self.context_of(Some(c)));
+
+        // Threads in order of priority, so the first one to accept wins
+        let mut current: Vec<(StateID, Slots)> = vec![];
+        let mut next = vec![];
+        let mut seen = FixedBitSet::with_capacity(self.states.len());
+
+        let slots = vec![None; 2 * self.capture_count()];
+        let context = (self.context_of(prev), context_after(symbols.peek()));
+        self.add_thread(&mut current, &mut seen, 0, slots, 0, context);
+
+        while let Some((end, c)) = symbols.next() {
+            let class = self.alphabet.class_of(c);
+            let context = (self.context_of_class(class), context_after(symbols.peek()));
+
+            seen.clear();
+            next.clear();
+            for (i, slots) in current.drain(..) {
+                if let Some(to) = self.states[i].transition_for(class) {
+                    self.add_thread(&mut next, &mut seen, to, slots, end, context);
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+        }
+
+        let (_, slots) = current
+            .into_iter()
+            .find(|&(i, _)| self.states[i].accept.is_some())?;
+
+        let groups = slots
+            .chunks(2)
+            .map(|slot| match slot {
+                [Some(start), Some(end)] => Some((*start, *end)),
+                _ => None,
+            })
+            .collect();
+        Some(groups)
+    }
+
+    /// Adds a thread for `idx` and each state reachable from it, recording
+    /// `offset` in the slots of capture states along the way.
+    fn add_thread(
+        &self,
+        threads: &mut Vec<(StateID, Slots)>,
+        seen: &mut FixedBitSet,
+        idx: StateID,
+        mut slots: Slots,
+        offset: usize,
+        context: (Context, Context),
+    ) {
+        if seen.put(idx) {
+            return;
+        }
+
+        let state = &self.states[idx];
+        if let Some(slot) = state.capture {
+            slots[slot] = Some(offset);
+        }
+        if state
+            .look
+            .is_some_and(|look| !look.holds(context.0, context.1))
+        {
+            return;
+        }
+
+        if state.accept.is_some() || !state.transitions.is_empty() {
+            threads.push((idx, slots.clone()));
+        }
+
+        for epsilon_idx in state.epsilon_transitions.iter() {
+            self.add_thread(threads, seen, *epsilon_idx, slots.clone(), offset, context);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "regex-syntax"))]
+mod tests {
+    use crate::nfa::{symbols, NFA};
+
+    fn captures(pattern: &str, input: &str) -> Option<Vec<Option<(usize, usize)>>> {
+        let nfa = NFA::from_regex(pattern).unwrap();
+        nfa.captures(symbols(input), None, None)
+    }
+
+    #[test]
+    fn groups() {
+        assert_eq!(
+            captures(r#""([^"]*)""#, r#""abc""#),
+            Some(vec![Some((1, 4))])
+        );
+        assert_eq!(
+            captures("([0-9]+)(?:e([0-9]+))?", "12e3"),
+            Some(vec![Some((0, 2)), Some((3, 4))])
+        );
+        assert_eq!(
+            captures("([0-9]+)(?:e([0-9]+))?", "12"),
+            Some(vec![Some((0, 2)), None])
+        );
+        assert_eq!(captures("(a)b", "ac"), None);
+    }
+
+    #[test]
+    fn priority() {
+        assert_eq!(
+            captures("(a*)(a*)", "aaa"),
+            Some(vec![Some((0, 3)), Some((3, 3))])
+        );
+        assert_eq!(
+            captures("(a|ab)(c|bcd)?", "abcd"),
+            Some(vec![Some((0, 1)), Some((1, 4))])
+        );
+    }
+
+    #[test]
+    fn names() {
+        let nfa = NFA::from_regex("(?P<int>[0-9]+)(?:.(?P<frac>[0-9]+))?").unwrap();
+
+        assert_eq!(
+            *nfa.capture_names,
+            vec![Some("int".to_string()), Some("frac".to_string())]
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use super::CharRange;
+
+/// An assertion about the chars around a position. A look state only follows
+/// its epsilon transition when its assertion holds.
+#[cfg_attr(not(feature = "regex-syntax"), allow(dead_code))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Look {
+    StartLine,
+    EndLine,
+    StartText,
+    EndText,
+    WordBoundary,
+    NotWordBoundary,
+    WordBoundaryAscii,
+    NotWordBoundaryAscii,
+}
+
+/// What assertions can tell about the char on one side of a position. `Edge`
+/// stands in for the start or end of the input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Context {
+    Edge,
+    Newline,
+    AsciiWord,
+    Word,
+    Other,
+}
+
+impl Look {
+    pub fn holds(self, prev: Context, next: Context) -> bool {
+        match self {
+            Look::StartLine => prev == Context::Edge || prev == Context::Newline,
+            Look::EndLine => next == Context::Edge || next == Context::Newline,
+            Look::StartText => prev == Context::Edge,
+            Look::EndText => next == Context::Edge,
+            Look::WordBoundary => prev.is_word() != next.is_word(),
+            Look::NotWordBoundary => prev.is_word() == next.is_word(),
+            Look::WordBoundaryAscii => prev.is_ascii_word() != next.is_ascii_word(),
+            Look::NotWordBoundaryAscii => prev.is_ascii_word() == next.is_ascii_word(),
+        }
+    }
+
+    /// Evaluates the assertion knowing only the char before the position.
+    /// Returns `None` when it depends on the char after the position too.
+    pub fn holds_after(self, prev: Context) -> Option<bool> {
+        match self {
+            Look::StartLine | Look::StartText => Some(self.holds(prev, Context::Edge)),
+            _ => None,
+        }
+    }
+
+    pub fn is_word_boundary(self) -> bool {
+        !matches!(
+            self,
+            Look::StartLine | Look::EndLine | Look::StartText | Look::EndText
+        )
+    }
+}
+
+impl Context {
+    /// Every context, in the order of their indexes.
+    pub const ALL: [Context; 5] = [
+        Context::Edge,
+        Context::Newline,
+        Context::AsciiWord,
+        Context::Word,
+        Context::Other,
+    ];
+
+    fn is_word(self) -> bool {
+        self == Context::AsciiWord || self == Context::Word
+    }
+
+    fn is_ascii_word(self) -> bool {
+        self == Context::AsciiWord
+    }
+}
+
+pub const ASCII_WORD: [CharRange; 4] = [('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')];
+
+/// Returns the ranges of chars matched by `\w`.
+#[cfg(feature = "regex-syntax")]
+pub fn unicode_word() -> Vec<CharRange> {
+    use regex_syntax::hir::{Class, HirKind};
+
+    match regex_syntax::Parser::new()
+        .parse(r"\w")
+        .map(|h| h.into_kind())
+    {
+        Ok(HirKind::Class(Class::Unicode(class))) => {
+            class.iter().map(|r| (r.start(), r.end())).collect()
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Without a parser, patterns cannot contain Unicode word boundaries.
+#[cfg(not(feature = "regex-syntax"))]
+pub fn unicode_word() -> Vec<CharRange> {
+    vec![]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Context, Look};
+
+    #[test]
+    fn lines() {
+        assert!(Look::StartLine.holds(Context::Newline, Context::Word));
+        assert!(Look::StartLine.holds(Context::Edge, Context::Word));
+        assert!(!Look::StartLine.holds(Context::Other, Context::Word));
+        assert!(Look::EndLine.holds(Context::Word, Context::Newline));
+        assert!(!Look::EndText.holds(Context::Word, Context::Newline));
+        assert_eq!(Look::EndLine.holds_after(Context::Word), None);
+    }
+
+    #[test]
+    fn word_boundaries() {
+        assert!(Look::WordBoundary.holds(Context::Word, Context::Other));
+        assert!(Look::WordBoundary.holds(Context::Edge, Context::AsciiWord));
+        assert!(!Look::WordBoundary.holds(Context::Word, Context::AsciiWord));
+        assert!(Look::WordBoundaryAscii.holds(Context::Word, Context::AsciiWord));
+        assert!(Look::NotWordBoundary.holds(Context::Other, Context::Newline));
+    }
+}
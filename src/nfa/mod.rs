@@ -1,28 +1,48 @@
-pub mod analyze;
+mod alphabet;
+mod analyze;
+mod captures;
+mod look;
+mod pikevm;
 mod regex;
+mod shortest;
 
-pub use crate::nfa::regex::{compile, Error as CompileError};
+pub use crate::nfa::alphabet::Alphabet;
+pub use crate::nfa::analyze::Prefixes;
+pub use crate::nfa::look::{Context, Look};
+pub use crate::nfa::pikevm::PikeVM;
+pub use crate::nfa::regex::{compile, compile_bytes, compile_with, Error as CompileError};
 
-use std::rc::Rc;
+use std::sync::Arc;
 
 use fixedbitset::FixedBitSet;
 
 #[derive(Clone, Debug)]
 pub struct NFA {
-    states: Rc<Vec<State>>,
+    pub(crate) states: Arc<Vec<State>>,
+    pub(crate) alphabet: Arc<Alphabet>,
+    /// The context of each class, when the NFA has look states.
+    pub(crate) contexts: Option<Arc<Vec<Context>>>,
+    /// The names of the capture groups of a pattern, if they have one.
+    pub(crate) capture_names: Arc<Vec<Option<String>>>,
 }
 
 #[derive(Clone, Debug)]
 pub struct State {
-    pub(super) accept: bool,
-    pub(super) transitions: Vec<Transition>,
-    pub(super) epsilon_transitions: Vec<StateID>,
+    pub(crate) accept: Option<usize>,
+    pub(crate) look: Option<Look>,
+    /// Marks where the trailing context of a pattern begins.
+    pub(crate) cut: bool,
+    /// The capture slot to record the current offset in.
+    pub(crate) capture: Option<usize>,
+    pub(crate) transitions: Vec<Transition>,
+    pub(crate) epsilon_transitions: Vec<StateID>,
 }
 
 #[derive(Clone, Debug)]
 pub struct Transition {
-    ranges: Vec<CharRange>,
-    to: StateID,
+    pub(crate) ranges: Vec<CharRange>,
+    classes: FixedBitSet,
+    pub(crate) to: StateID,
 }
 
 pub struct ExecutionState {
@@ -32,21 +52,194 @@ pub struct ExecutionState {
 
 pub type States = FixedBitSet;
 pub type CharRange = (char, char);
-type StateID = usize;
+pub type StateID = usize;
+
+/// Yields each char of `input` along with the offset just past it.
+pub fn symbols(input: &str) -> impl Iterator<Item = (usize, char)> + Clone + '_ {
+    input.char_indices().map(|(i, c)| (i + c.len_utf8(), c))
+}
+
+/// Yields each byte of `input` as the char with the same value, along with the
+/// offset just past it. These are the symbols of NFAs built by
+/// `compile_bytes`.
+pub fn byte_symbols(input: &[u8]) -> impl Iterator<Item = (usize, char)> + Clone + '_ {
+    input
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| (i + 1, char::from(b)))
+}
 
 impl NFA {
     pub fn from_regex(pattern: &str) -> Result<NFA, CompileError> {
         compile(pattern)
     }
 
-    pub fn new(states: Vec<State>) -> NFA {
+    pub fn from_regex_bytes(pattern: &str) -> Result<NFA, CompileError> {
+        compile_bytes(pattern)
+    }
+
+    pub fn from_regex_case_insensitive(pattern: &str, bytes: bool) -> Result<NFA, CompileError> {
+        compile_with(pattern, bytes, true)
+    }
+
+    pub fn from_literal(literal: &str) -> NFA {
+        NFA::from_symbols(literal.chars())
+    }
+
+    pub fn from_literal_bytes(literal: &[u8]) -> NFA {
+        NFA::from_symbols(literal.iter().map(|&b| char::from(b)))
+    }
+
+    /// Matches `literal` regardless of case, over chars or over the bytes of
+    /// UTF-8 encoded input.
+    pub fn from_literal_case_insensitive(literal: &str, bytes: bool) -> NFA {
+        let mut states = vec![];
+
+        for c in literal.chars() {
+            let variants = regex::case_variants(c);
+            if !bytes {
+                let ranges = variants.iter().map(|&v| (v, v)).collect::<Vec<_>>();
+                states.push(State::new(
+                    &[Transition::ranges(&ranges, states.len() + 1)],
+                    &[],
+                ));
+                continue;
+            }
+
+            // Branch to the encoding of each variant, which may differ in length
+            let branch = states.len();
+            states.push(State::new(&[], &[]));
+
+            let mut ends = vec![];
+            for v in variants {
+                let start = states.len();
+                states[branch].epsilon_transitions.push(start);
+
+                let mut buf = [0; 4];
+                for b in v.encode_utf8(&mut buf).bytes().map(char::from) {
+                    states.push(State::new(
+                        &[Transition::ranges(&[(b, b)], states.len() + 1)],
+                        &[],
+                    ));
+                }
+                ends.push(states.len() - 1);
+            }
+
+            let join = states.len();
+            for end in ends {
+                states[end].transitions[0].to = join;
+            }
+            states.push(State::new(&[], &[join + 1]));
+        }
+        states.push(State::accept(&[], &[]));
+
+        NFA::new(states)
+    }
+
+    fn from_symbols(symbols: impl Iterator<Item = char>) -> NFA {
+        let mut states = symbols
+            .enumerate()
+            .map(|(i, c)| State::new(&[Transition::ranges(&[(c, c)], i + 1)], &[]))
+            .collect::<Vec<_>>();
+        states.push(State::accept(&[], &[]));
+
+        NFA::new(states)
+    }
+
+    pub fn new(mut states: Vec<State>) -> NFA {
+        let looks = states.iter().filter_map(|s| s.look).collect::<Vec<_>>();
+        let has = |a, b| looks.contains(&a) || looks.contains(&b);
+
+        // Assertions need every class to be uniformly newline, word or other
+        let newline = vec![('\n', '\n')];
+        let mut context_sets = vec![];
+        if has(Look::WordBoundary, Look::NotWordBoundary) {
+            context_sets.push((look::unicode_word(), Context::Word));
+        }
+        if has(Look::WordBoundaryAscii, Look::NotWordBoundaryAscii) {
+            context_sets.push((look::ASCII_WORD.to_vec(), Context::AsciiWord));
+        }
+        if !looks.is_empty() {
+            context_sets.push((newline, Context::Newline));
+        }
+
+        let alphabet = Alphabet::new(
+            states
+                .iter()
+                .flat_map(|s| s.transitions.iter().map(|t| &t.ranges[..]))
+                .chain(context_sets.iter().map(|(set, _)| &set[..])),
+        );
+
+        for t in states.iter_mut().flat_map(|s| s.transitions.iter_mut()) {
+            t.classes = FixedBitSet::with_capacity(alphabet.len());
+            for range in t.ranges.iter() {
+                t.classes.extend(alphabet.classes(*range));
+            }
+        }
+
+        let contexts = if looks.is_empty() {
+            None
+        } else {
+            let mut contexts = vec![Context::Other; alphabet.len()];
+            for (set, context) in context_sets.iter() {
+                for class in set.iter().flat_map(|r| alphabet.classes(*r)) {
+                    contexts[class] = *context;
+                }
+            }
+            Some(Arc::new(contexts))
+        };
+
         NFA {
-            states: Rc::new(states),
+            states: Arc::new(states),
+            alphabet: Arc::new(alphabet),
+            contexts,
+            capture_names: Arc::new(vec![]),
+        }
+    }
+
+    /// Builds an NFA matching `head` followed by `trailing`, with a cut state
+    /// between them so that `cut` can find where the head of a match ends.
+    pub fn with_trailing_context(head: &NFA, trailing: &NFA) -> NFA {
+        let cut = head.states.len();
+        let mut states = head
+            .states
+            .iter()
+            .map(|s| {
+                let mut state = s.clone();
+                if state.accept.take().is_some() {
+                    state.epsilon_transitions.push(cut);
+                }
+                state
+            })
+            .collect::<Vec<_>>();
+
+        states.push(State::cut(cut + 1));
+        states.extend(trailing.states.iter().map(|s| s.offset(cut + 1)));
+
+        NFA::new(states)
+    }
+
+    /// Combines several NFAs into one whose start state branches to each of
+    /// them. Accept states are tagged with the index of the NFA they came from.
+    pub fn union(nfas: &[NFA]) -> NFA {
+        let mut states = vec![State::new(&[], &[])];
+
+        for (i, nfa) in nfas.iter().enumerate() {
+            let offset = states.len();
+            states[0].epsilon_transitions.push(offset);
+
+            states.extend(nfa.states.iter().map(|s| {
+                let mut state = s.offset(offset);
+                state.accept = state.accept.map(|_| i);
+                state
+            }));
         }
+
+        NFA::new(states)
     }
 
     pub fn has_match_state(&self, states: &States) -> bool {
-        states.ones().any(|i| self.states[i].accept)
+        states.ones().any(|i| self.states[i].accept.is_some())
     }
 
     pub fn is_dead_state(&self, states: &States) -> bool {
@@ -66,16 +259,40 @@ impl NFA {
         states
     }
 
+    pub fn has_word_boundaries(&self) -> bool {
+        self.states
+            .iter()
+            .any(|s| s.look.is_some_and(Look::is_word_boundary))
+    }
+
+    /// Returns the context of the chars in `class`. Without look states,
+    /// every class is `Context::Other`.
     #[inline]
-    pub fn initialize_states(&self, states: &mut States) {
+    pub fn context_of_class(&self, class: usize) -> Context {
+        match &self.contexts {
+            Some(contexts) => contexts[class],
+            None => Context::Other,
+        }
+    }
+
+    /// Returns the context of the char before a position, if any.
+    pub fn context_of(&self, c: Option<char>) -> Context {
+        match c {
+            Some(c) => self.context_of_class(self.alphabet.class_of(c)),
+            None => Context::Edge,
+        }
+    }
+
+    /// Adds the start state and the states reachable from it at a position
+    /// following `prev`.
+    #[inline]
+    pub fn initialize_states(&self, states: &mut States, prev: Context) {
         states.clear();
-        self.add_states(states, 0);
+        self.add_states(states, 0, &|look| look.holds_after(prev));
     }
 
-    #[allow(dead_code)]
     pub fn matches(&self, input: &str, state: &mut ExecutionState) -> Option<usize> {
-        state.current.clear();
-        self.add_states(&mut state.current, 0);
+        self.initialize_states(&mut state.current, Context::Edge);
 
         if input.is_empty() {
             return if self.has_match_state(&state.current) {
@@ -102,26 +319,190 @@ impl NFA {
         match_len
     }
 
+    /// Picks the rule matched by a set of states. When several rules accept,
+    /// the rule with the highest precedence wins, followed by the rule that
+    /// was defined first.
+    pub fn accepting_rule(
+        &self,
+        states: impl Iterator<Item = StateID>,
+        precedences: &[u8],
+    ) -> Option<usize> {
+        states
+            .filter_map(|i| self.states[i].accept)
+            .min_by_key(|&rule| (std::cmp::Reverse(precedences[rule]), rule))
+    }
+
     pub fn step(&self, current: &States, c: char, next: &mut States) {
+        self.step_class(current.ones(), self.alphabet.class_of(c), next);
+    }
+
+    pub fn step_class(
+        &self,
+        current: impl Iterator<Item = StateID>,
+        class: usize,
+        next: &mut States,
+    ) {
         next.clear();
 
-        for i in current.ones() {
-            let state = &self.states[i];
+        let prev = self.context_of_class(class);
+        let holds = |look: Look| look.holds_after(prev);
+        for i in current {
+            if let Some(to) = self.states[i].transition_for(class) {
+                self.visit_states(next, to, &holds);
+            }
+        }
+        self.retain_states(next, &holds);
+    }
+
+    /// Resolves the look states left in `current` now that the context after
+    /// the position is known as well.
+    pub fn resolve(
+        &self,
+        current: impl Iterator<Item = StateID>,
+        prev: Context,
+        next: Context,
+        resolved: &mut States,
+    ) {
+        resolved.clear();
+
+        let holds = |look: Look| Some(look.holds(prev, next));
+        for i in current {
+            match self.states[i].look {
+                Some(_) => self.visit_states(resolved, i, &holds),
+                None => resolved.insert(i),
+            }
+        }
+        self.retain_states(resolved, &holds);
+    }
 
-            if let Some(to) = state.transition_for(c) {
-                self.add_states(next, to);
+    /// Returns the length of the head of a match of an NFA built by
+    /// `with_trailing_context`, where `symbols` are those of the whole match,
+    /// and `prev` and `next` are the chars around it. When the match can be
+    /// split in several ways, the longest head wins.
+    pub fn cut(
+        &self,
+        symbols: impl Iterator<Item = (usize, char)>,
+        prev: Option<char>,
+        next: Option<char>,
+    ) -> Option<usize> {
+        let mut symbols = symbols.peekable();
+        let after = self.context_of(next);
+        let context_after =
+            |c: Option<&(usize, char)>| c.map_or(after, |&(_, c)| self.context_of(Some(c)));
+
+        // The cut offset of each live state, keeping the largest when several
+        // paths reach the same state
+        let mut current = vec![None; self.states.len()];
+        let mut next = vec![None; self.states.len()];
+
+        let context = (self.context_of(prev), context_after(symbols.peek()));
+        self.add_cut_states(&mut current, 0, 0, 0, context);
+
+        while let Some((end, c)) = symbols.next() {
+            let class = self.alphabet.class_of(c);
+            let context = (self.context_of_class(class), context_after(symbols.peek()));
+
+            next.iter_mut().for_each(|cut| *cut = None);
+            for (i, cut) in current.iter().enumerate() {
+                if let (Some(cut), Some(to)) = (cut, self.states[i].transition_for(class)) {
+                    self.add_cut_states(&mut next, to, *cut, end, context);
+                }
             }
+            std::mem::swap(&mut current, &mut next);
         }
+
+        current
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| self.states[i].accept.is_some())
+            .filter_map(|(_, &cut)| cut)
+            .max()
     }
 
-    fn add_states(&self, states: &mut States, idx: StateID) {
-        let state = &self.states[idx as usize];
-        if state.accept || !state.transitions.is_empty() {
-            states.insert(idx as usize);
+    /// Adds `idx` and the states reachable from it to `states` along with
+    /// their cut offsets, where `context` holds the contexts around `offset`.
+    fn add_cut_states(
+        &self,
+        states: &mut [Option<usize>],
+        idx: StateID,
+        cut: usize,
+        offset: usize,
+        context: (Context, Context),
+    ) {
+        let state = &self.states[idx];
+        let cut = if state.cut { offset } else { cut };
+        if states[idx].is_some_and(|c| c >= cut) {
+            return;
+        }
+        states[idx] = Some(cut);
+
+        if state
+            .look
+            .is_some_and(|look| !look.holds(context.0, context.1))
+        {
+            return;
         }
 
         for epsilon_idx in state.epsilon_transitions.iter() {
-            self.add_states(states, *epsilon_idx);
+            self.add_cut_states(states, *epsilon_idx, cut, offset, context);
+        }
+    }
+
+    /// Adds `idx` and the states reachable from it through epsilon
+    /// transitions. `holds` evaluates the assertions of look states, and look
+    /// states it cannot evaluate yet are added to be resolved later.
+    pub fn add_states(
+        &self,
+        states: &mut States,
+        idx: StateID,
+        holds: &impl Fn(Look) -> Option<bool>,
+    ) {
+        self.visit_states(states, idx, holds);
+        self.retain_states(states, holds);
+    }
+
+    /// Marks `idx` and the states reachable from it through epsilon
+    /// transitions in `states`. Marked states are not visited again, so that
+    /// epsilon cycles such as those of `(a*)*` terminate.
+    fn visit_states(
+        &self,
+        states: &mut States,
+        idx: StateID,
+        holds: &impl Fn(Look) -> Option<bool>,
+    ) {
+        if states.put(idx) {
+            return;
+        }
+
+        let state = &self.states[idx];
+        if state.look.is_some_and(|look| holds(look) != Some(true)) {
+            return;
+        }
+
+        for epsilon_idx in state.epsilon_transitions.iter() {
+            self.visit_states(states, *epsilon_idx, holds);
+        }
+    }
+
+    /// Unmarks the states that only lead to others through epsilon
+    /// transitions, keeping those that accept or consume input, and the look
+    /// states `holds` cannot evaluate yet.
+    fn retain_states(&self, states: &mut States, holds: &impl Fn(Look) -> Option<bool>) {
+        for block in 0..states.as_slice().len() {
+            let mut bits = states.as_slice()[block];
+            while bits != 0 {
+                let idx = block * 32 + bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+
+                let state = &self.states[idx];
+                let keep = match state.look {
+                    Some(look) => holds(look).is_none(),
+                    None => state.accept.is_some() || !state.transitions.is_empty(),
+                };
+                if !keep {
+                    states.set(idx, false);
+                }
+            }
         }
     }
 }
@@ -129,7 +510,10 @@ impl NFA {
 impl State {
     pub fn new(transitions: &[Transition], epsilon_transitions: &[StateID]) -> State {
         State {
-            accept: false,
+            accept: None,
+            look: None,
+            cut: false,
+            capture: None,
             transitions: transitions.to_vec(),
             epsilon_transitions: epsilon_transitions.to_vec(),
         }
@@ -137,12 +521,56 @@ impl State {
 
     pub fn accept(transitions: &[Transition], epsilon_transitions: &[StateID]) -> State {
         State {
-            accept: true,
+            accept: Some(0),
+            look: None,
+            cut: false,
+            capture: None,
             transitions: transitions.to_vec(),
             epsilon_transitions: epsilon_transitions.to_vec(),
         }
     }
 
+    /// A state that only continues to `to` when `look` holds.
+    #[cfg(feature = "regex-syntax")]
+    pub fn look(look: Look, to: StateID) -> State {
+        State {
+            accept: None,
+            look: Some(look),
+            cut: false,
+            capture: None,
+            transitions: vec![],
+            epsilon_transitions: vec![to],
+        }
+    }
+
+    /// A state that marks the end of the head of a pattern with trailing
+    /// context, which continues to the trailing context at `to`.
+    pub fn cut(to: StateID) -> State {
+        State {
+            accept: None,
+            look: None,
+            cut: true,
+            capture: None,
+            transitions: vec![],
+            epsilon_transitions: vec![to],
+        }
+    }
+
+    /// A state that records the current offset in capture `slot` and then
+    /// continues to `to`.
+    #[cfg(feature = "regex-syntax")]
+    pub fn capture(slot: usize, to: StateID) -> State {
+        State {
+            accept: None,
+            look: None,
+            cut: false,
+            capture: Some(slot),
+            transitions: vec![],
+            epsilon_transitions: vec![to],
+        }
+    }
+
+    #[cfg(feature = "regex-syntax")]
     pub fn patch(&mut self, from: StateID, to: StateID) {
         for t in self.transitions.iter_mut() {
             if t.to == from {
@@ -157,17 +585,30 @@ impl State {
         }
     }
 
-    fn transition_for(&self, c: char) -> Option<StateID> {
+    fn offset(&self, offset: usize) -> State {
+        State {
+            accept: self.accept,
+            look: self.look,
+            cut: self.cut,
+            capture: self.capture,
+            transitions: self
+                .transitions
+                .iter()
+                .map(|t| Transition::ranges(&t.ranges, t.to + offset))
+                .collect(),
+            epsilon_transitions: self
+                .epsilon_transitions
+                .iter()
+                .map(|t| t + offset)
+                .collect(),
+        }
+    }
+
+    fn transition_for(&self, class: usize) -> Option<StateID> {
         self.transitions
             .iter()
-            .filter_map(|t| {
-                if t.ranges.iter().any(|(l, h)| c >= *l && c <= *h) {
-                    return Some(t.to);
-                }
-
-                None
-            })
-            .nth(0)
+            .find(|t| t.classes.contains(class))
+            .map(|t| t.to)
     }
 }
 
@@ -175,6 +616,7 @@ impl Transition {
     pub fn ranges(sets: &[CharRange], to: StateID) -> Transition {
         Transition {
             ranges: sets.to_owned(),
+            classes: FixedBitSet::with_capacity(0),
             to,
         }
     }
@@ -0,0 +1,229 @@
+use std::sync::Arc;
+
+use super::{Context, Look, Prefixes, StateID, NFA};
+
+/// Simulates every rule of a union NFA at once, in the style of a Pike VM.
+///
+/// Each thread is an NFA state, and accept states are tagged with the rule
+/// they belong to, so a single pass over the input finds the longest match of
+/// any rule. Ties are broken by `NFA::accepting_rule`, exactly as they are
+/// when building a DFA.
+///
+/// Matches are only reported once the char after them has been seen, since
+/// assertions such as `$` and `\b` depend on it.
+#[derive(Clone, Debug)]
+pub struct PikeVM {
+    nfa: NFA,
+    precedences: Vec<u8>,
+    prefixes: Arc<Prefixes>,
+    current: Threads,
+    next: Threads,
+    resolved: Threads,
+    stack: Vec<StateID>,
+}
+
+/// A sparse set of threads, which can be cleared in constant time and
+/// iterated in the order threads were added.
+#[derive(Clone, Debug)]
+struct Threads {
+    dense: Vec<StateID>,
+    sparse: Vec<usize>,
+}
+
+impl PikeVM {
+    pub fn new(nfa: NFA, precedences: Vec<u8>, prefixes: Arc<Prefixes>) -> PikeVM {
+        let len = nfa.states.len();
+
+        PikeVM {
+            nfa,
+            precedences,
+            prefixes,
+            current: Threads::new(len),
+            next: Threads::new(len),
+            resolved: Threads::new(len),
+            stack: vec![],
+        }
+    }
+
+    /// Returns the rule and length in bytes of the longest non-empty match at
+    /// the start of the input yielding `symbols`, which follows the char
+    /// `prev`. Only the rules that `prefixes` maps the first symbol to are
+    /// started.
+    pub fn longest_match(
+        &mut self,
+        symbols: impl Iterator<Item = (usize, char)>,
+        prev: Option<char>,
+    ) -> Option<(usize, usize)> {
+        let mut symbols = symbols.peekable();
+        let &(_, c) = symbols.peek()?;
+        let mut prev = self.nfa.context_of(prev);
+
+        self.current.clear();
+        for rule in self.prefixes.rules_for(c).ones() {
+            let start = self.nfa.states[0].epsilon_transitions[rule];
+            let holds = |look: Look| look.holds_after(prev);
+            self.current.add(&self.nfa, &mut self.stack, start, &holds);
+        }
+
+        let mut best = None;
+        let mut len = 0;
+
+        for (end, c) in symbols {
+            let class = self.nfa.alphabet.class_of(c);
+            let next = self.nfa.context_of_class(class);
+
+            self.resolve(prev, next);
+            if let Some(rule) = self.accepting_rule(len) {
+                best = Some((rule, len));
+            }
+
+            self.next.clear();
+            for &thread in self.current.dense.iter() {
+                if let Some(to) = self.nfa.states[thread].transition_for(class) {
+                    let holds = |look: Look| look.holds_after(next);
+                    self.next.add(&self.nfa, &mut self.stack, to, &holds);
+                }
+            }
+            std::mem::swap(&mut self.current, &mut self.next);
+
+            if self.current.dense.is_empty() {
+                return best;
+            }
+
+            prev = next;
+            len = end;
+        }
+
+        self.resolve(prev, Context::Edge);
+        if let Some(rule) = self.accepting_rule(len) {
+            best = Some((rule, len));
+        }
+
+        best
+    }
+
+    /// Follows the look states among the current threads that hold between
+    /// `prev` and `next`.
+    fn resolve(&mut self, prev: Context, next: Context) {
+        if self.nfa.contexts.is_none() {
+            return;
+        }
+
+        self.resolved.clear();
+        for &thread in self.current.dense.iter() {
+            let holds = |look: Look| Some(look.holds(prev, next));
+            self.resolved
+                .add(&self.nfa, &mut self.stack, thread, &holds);
+        }
+        std::mem::swap(&mut self.current, &mut self.resolved);
+    }
+
+    /// Returns the rule accepted by the current threads, ignoring empty
+    /// matches.
+    fn accepting_rule(&self, len: usize) -> Option<usize> {
+        if len == 0 {
+            return None;
+        }
+
+        let threads = self.current.dense.iter().copied();
+        self.nfa.accepting_rule(threads, &self.precedences)
+    }
+}
+
+impl Threads {
+    fn new(len: usize) -> Threads {
+        Threads {
+            dense: Vec::with_capacity(len),
+            sparse: vec![0; len],
+        }
+    }
+
+    fn clear(&mut self) {
+        self.dense.clear();
+    }
+
+    /// Adds a thread for `state` and every state reachable from it through
+    /// epsilon transitions, using `stack` in place of recursion. Look states
+    /// are only followed when `holds` says their assertion holds.
+    fn add(
+        &mut self,
+        nfa: &NFA,
+        stack: &mut Vec<StateID>,
+        state: StateID,
+        holds: &impl Fn(Look) -> Option<bool>,
+    ) {
+        stack.push(state);
+
+        while let Some(state) = stack.pop() {
+            if !self.insert(state) {
+                continue;
+            }
+
+            let state = &nfa.states[state];
+            if state.look.is_some_and(|look| holds(look) != Some(true)) {
+                continue;
+            }
+
+            stack.extend(state.epsilon_transitions.iter().rev());
+        }
+    }
+
+    /// Adds `state`, returning false if it was already present.
+    fn insert(&mut self, state: StateID) -> bool {
+        let i = self.sparse[state];
+        if i < self.dense.len() && self.dense[i] == state {
+            return false;
+        }
+
+        self.sparse[state] = self.dense.len();
+        self.dense.push(state);
+        true
+    }
+}
+
+#[cfg(all(test, feature = "regex-syntax"))]
+mod tests {
+    use std::sync::Arc;
+
+    use super::PikeVM;
+    use crate::dfa::DFA;
+    use crate::nfa::{symbols, Prefixes, NFA};
+
+    fn vm(patterns: &[&str], precedences: &[u8]) -> (PikeVM, DFA) {
+        let nfas = patterns
+            .iter()
+            .map(|p| NFA::from_regex(p).unwrap())
+            .collect::<Vec<_>>();
+        let ranges = nfas.iter().map(|n| n.starting_ranges()).collect::<Vec<_>>();
+        let nfa = NFA::union(&nfas);
+        let dfa = DFA::from_nfa(&nfa, precedences);
+
+        let prefixes = Arc::new(Prefixes::new(&ranges));
+        (PikeVM::new(nfa, precedences.to_vec(), prefixes), dfa)
+    }
+
+    #[test]
+    fn matches_dfa() {
+        let (mut vm, dfa) = vm(
+            &[r"\w+", r#""[^"]*""#, "[0-9]+", r"\s+", "if"],
+            &[0, 0, 0, 0, 1],
+        );
+
+        let inputs = [
+            "hello",
+            "123",
+            "\"ünïcode\" x",
+            "  \n",
+            "if",
+            "iffy",
+            "!",
+            "",
+        ];
+        for input in inputs.iter() {
+            assert_eq!(
+                vm.longest_match(symbols(input), None),
+                dfa.longest_match(symbols(input), None)
+            );
+        }
+    }
+}
@@ -1,27 +1,145 @@
+use std::fmt;
+
+#[cfg(feature = "regex-syntax")]
 use regex_syntax::hir::{self, Hir, HirKind};
-use regex_syntax::Parser;
+#[cfg(feature = "regex-syntax")]
+use regex_syntax::utf8::Utf8Sequences;
+#[cfg(feature = "regex-syntax")]
+use regex_syntax::ParserBuilder;
+
+#[cfg(feature = "regex-syntax")]
+use std::sync::Arc;
 
-use super::{State, Transition, NFA};
+use super::NFA;
+#[cfg(feature = "regex-syntax")]
+use super::{Look, State, Transition};
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Error {
-    InvalidPattern(regex_syntax::Error),
+    #[cfg(feature = "regex-syntax")]
+    InvalidPattern(Box<regex_syntax::Error>),
     UnsupportedFeature(&'static str),
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            #[cfg(feature = "regex-syntax")]
+            Error::InvalidPattern(e) => e.fmt(f),
+            Error::UnsupportedFeature(feature) => write!(f, "unsupported: {}", feature),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Compiles a pattern into an NFA over chars.
 pub fn compile(pattern: &str) -> Result<NFA, Error> {
-    let hir = Parser::new()
+    compile_with(pattern, false, false)
+}
+
+/// Compiles a pattern into an NFA over the bytes of UTF-8 encoded input,
+/// where each byte is represented by the char with the same value (U+0000
+/// through U+00FF). Chars in the pattern match their UTF-8 encoding, while
+/// byte literals and classes such as `(?-u:\xFF)` match raw bytes.
+pub fn compile_bytes(pattern: &str) -> Result<NFA, Error> {
+    compile_with(pattern, true, false)
+}
+
+/// Compiles a pattern over chars, or over bytes like `compile_bytes`. When
+/// `case_insensitive` is set, the pattern is compiled as if it began with
+/// `(?i)`.
+#[cfg(not(feature = "regex-syntax"))]
+pub fn compile_with(_pattern: &str, _bytes: bool, _case_insensitive: bool) -> Result<NFA, Error> {
+    Err(Error::UnsupportedFeature(
+        "patterns without the regex-syntax feature",
+    ))
+}
+
+/// Compiles a pattern over chars, or over bytes like `compile_bytes`. When
+/// `case_insensitive` is set, the pattern is compiled as if it began with
+/// `(?i)`.
+#[cfg(feature = "regex-syntax")]
+pub fn compile_with(pattern: &str, bytes: bool, case_insensitive: bool) -> Result<NFA, Error> {
+    let hir = ParserBuilder::new()
+        .allow_invalid_utf8(bytes)
+        .case_insensitive(case_insensitive)
+        .multi_line(true)
+        .build()
         .parse(pattern)
-        .map_err(Error::InvalidPattern)?;
+        .map_err(|e| Error::InvalidPattern(Box::new(e)))?;
 
     let mut states = vec![];
-    compile_hir(&hir, &mut states)?;
+    compile_hir(&hir, &mut states, bytes)?;
     states.push(State::accept(&[], &[]));
 
-    Ok(NFA::new(states))
+    let mut names = vec![];
+    capture_names(&hir, &mut names);
+
+    let mut nfa = NFA::new(states);
+    nfa.capture_names = Arc::new(names);
+    Ok(nfa)
+}
+
+/// Returns `c` along with the chars it is equivalent to under Unicode simple
+/// case folding.
+#[cfg(feature = "regex-syntax")]
+pub fn case_variants(c: char) -> Vec<char> {
+    let mut class = hir::ClassUnicode::new(vec![hir::ClassUnicodeRange::new(c, c)]);
+    class.case_fold_simple();
+
+    class.iter().flat_map(|r| r.start()..=r.end()).collect()
+}
+
+/// Without the case folding tables of `regex-syntax`, only the chars that
+/// `c` lowercases or uppercases to are considered.
+#[cfg(not(feature = "regex-syntax"))]
+pub fn case_variants(c: char) -> Vec<char> {
+    let mut variants = vec![c];
+    let mut lower = c.to_lowercase();
+    if let (Some(v), None) = (lower.next(), lower.next()) {
+        variants.push(v);
+    }
+    let mut upper = c.to_uppercase();
+    if let (Some(v), None) = (upper.next(), upper.next()) {
+        variants.push(v);
+    }
+
+    variants.sort_unstable();
+    variants.dedup();
+    variants
+}
+
+/// Collects the names of the capture groups in `hir`, by index.
+#[cfg(feature = "regex-syntax")]
+fn capture_names(hir: &Hir, names: &mut Vec<Option<String>>) {
+    match hir.kind() {
+        HirKind::Group(group) => {
+            let (index, name) = match &group.kind {
+                hir::GroupKind::CaptureIndex(index) => (*index, None),
+                hir::GroupKind::CaptureName { name, index } => (*index, Some(name.clone())),
+                hir::GroupKind::NonCapturing => return capture_names(&group.hir, names),
+            };
+
+            let index = index as usize;
+            if names.len() < index {
+                names.resize(index, None);
+            }
+            names[index - 1] = name;
+            capture_names(&group.hir, names);
+        }
+        HirKind::Alternation(children) | HirKind::Concat(children) => {
+            for child in children.iter() {
+                capture_names(child, names);
+            }
+        }
+        HirKind::Repetition(rep) => capture_names(&rep.hir, names),
+        _ => {}
+    }
 }
 
-fn compile_hir(hir: &Hir, states: &mut Vec<State>) -> Result<(), Error> {
+#[cfg(feature = "regex-syntax")]
+fn compile_hir(hir: &Hir, states: &mut Vec<State>, bytes: bool) -> Result<(), Error> {
     match hir.kind() {
         HirKind::Alternation(alternatives) => {
             let mut fixups = vec![];
@@ -33,7 +151,7 @@ fn compile_hir(hir: &Hir, states: &mut Vec<State>) -> Result<(), Error> {
                     states.push(State::new(&[], &[start + 1, 0]));
                 }
 
-                compile_hir(&alt, states)?;
+                compile_hir(alt, states, bytes)?;
                 fixups.push((states.len() - 1, states.len()));
 
                 if needs_branch {
@@ -47,33 +165,56 @@ fn compile_hir(hir: &Hir, states: &mut Vec<State>) -> Result<(), Error> {
                 state.patch(target, end);
             }
         }
-        HirKind::Anchor(_) => {
-            return Err(Error::UnsupportedFeature("anchor assertions"));
+        HirKind::Anchor(anchor) => {
+            let look = match anchor {
+                hir::Anchor::StartLine => Look::StartLine,
+                hir::Anchor::EndLine => Look::EndLine,
+                hir::Anchor::StartText => Look::StartText,
+                hir::Anchor::EndText => Look::EndText,
+            };
+            states.push(State::look(look, states.len() + 1));
+        }
+        HirKind::Class(hir::Class::Unicode(unicode)) if bytes => {
+            compile_utf8_class(unicode, states);
         }
-        HirKind::Class(class) => match class {
-            hir::Class::Unicode(unicode) => {
-                let ranges = unicode
+        HirKind::Class(class) => {
+            let ranges = match class {
+                hir::Class::Unicode(unicode) => unicode
                     .iter()
                     .map(|r| (r.start(), r.end()))
-                    .collect::<Vec<_>>();
+                    .collect::<Vec<_>>(),
+                // Without invalid UTF-8, byte classes only contain ASCII
+                hir::Class::Bytes(class) => class
+                    .iter()
+                    .map(|r| (char::from(r.start()), char::from(r.end())))
+                    .collect::<Vec<_>>(),
+            };
 
-                states.push(State::new(
-                    &[Transition::ranges(&ranges, states.len() + 1)],
-                    &[],
-                ));
-            }
-            hir::Class::Bytes(_) => {
-                return Err(Error::UnsupportedFeature("byte classes"));
-            }
-        },
+            states.push(State::new(
+                &[Transition::ranges(&ranges, states.len() + 1)],
+                &[],
+            ));
+        }
         HirKind::Concat(children) => {
             for c in children.iter() {
-                compile_hir(&c, states)?;
+                compile_hir(c, states, bytes)?;
             }
         }
         HirKind::Empty => {}
-        HirKind::Group(group) => {
-            compile_hir(&group.hir, states)?;
+        HirKind::Group(group) => match group.kind {
+            hir::GroupKind::NonCapturing => compile_hir(&group.hir, states, bytes)?,
+            hir::GroupKind::CaptureIndex(index) | hir::GroupKind::CaptureName { index, .. } => {
+                let slot = 2 * (index as usize - 1);
+                states.push(State::capture(slot, states.len() + 1));
+                compile_hir(&group.hir, states, bytes)?;
+                states.push(State::capture(slot + 1, states.len() + 1));
+            }
+        },
+        HirKind::Literal(hir::Literal::Unicode(c)) if bytes => {
+            let mut buf = [0; 4];
+            for b in c.encode_utf8(&mut buf).bytes() {
+                push_byte_ranges(states, &[(b, b)]);
+            }
         }
         HirKind::Literal(hir::Literal::Unicode(c)) => {
             states.push(State::new(
@@ -81,66 +222,149 @@ fn compile_hir(hir: &Hir, states: &mut Vec<State>) -> Result<(), Error> {
                 &[],
             ));
         }
-        HirKind::Literal(hir::Literal::Byte(_)) => {
-            return Err(Error::UnsupportedFeature("byte literals"));
+        HirKind::Literal(hir::Literal::Byte(b)) => {
+            push_byte_ranges(states, &[(*b, *b)]);
         }
         HirKind::Repetition(rep) => {
             if !rep.greedy {
-                return Err(Error::UnsupportedFeature("non-greedy repetitions"));
+                return Err(Error::UnsupportedFeature(
+                    "non-greedy repetitions, use `LexiconBuilder::shortest_pattern` instead",
+                ));
             }
 
             let start = states.len();
             match rep.kind {
                 hir::RepetitionKind::ZeroOrOne => {
                     states.push(State::new(&[], &[0, 0]));
-                    compile_hir(&rep.hir, states)?;
+                    compile_hir(&rep.hir, states, bytes)?;
                     states[start] = State::new(&[], &[start + 1, states.len()]);
                 }
                 hir::RepetitionKind::ZeroOrMore => {
                     states.push(State::new(&[], &[0, 0]));
-                    compile_hir(&rep.hir, states)?;
+                    compile_hir(&rep.hir, states, bytes)?;
                     states.push(State::new(&[], &[start + 1, states.len() + 1]));
                     states[start] = State::new(&[], &[start + 1, states.len()]);
                 }
                 hir::RepetitionKind::OneOrMore => {
-                    compile_hir(&rep.hir, states)?;
+                    compile_hir(&rep.hir, states, bytes)?;
                     states.push(State::new(&[], &[start, states.len() + 1]));
                 }
                 hir::RepetitionKind::Range(ref range) => match range {
                     hir::RepetitionRange::Exactly(n) => {
                         for _ in 0..*n {
-                            compile_hir(&rep.hir, states)?
+                            compile_hir(&rep.hir, states, bytes)?
                         }
                     }
+                    hir::RepetitionRange::AtLeast(0) => {
+                        states.push(State::new(&[], &[0, 0]));
+                        compile_hir(&rep.hir, states, bytes)?;
+                        states.push(State::new(&[], &[start + 1, states.len() + 1]));
+                        states[start] = State::new(&[], &[start + 1, states.len()]);
+                    }
                     hir::RepetitionRange::AtLeast(n) => {
-                        for _ in 0..*n {
-                            compile_hir(&rep.hir, states)?;
+                        for _ in 1..*n {
+                            compile_hir(&rep.hir, states, bytes)?;
                         }
-                        states.push(State::new(&[], &[states.len() - 1, states.len() + 1]));
+                        let last = states.len();
+                        compile_hir(&rep.hir, states, bytes)?;
+                        states.push(State::new(&[], &[last, states.len() + 1]));
                     }
                     hir::RepetitionRange::Bounded(low, high) => {
                         for _ in 0..*low {
-                            compile_hir(&rep.hir, states)?;
+                            compile_hir(&rep.hir, states, bytes)?;
                         }
 
                         for _ in 0..(*high - *low) {
                             let idx = states.len();
                             states.push(State::new(&[], &[0, 0]));
-                            compile_hir(&rep.hir, states)?;
+                            compile_hir(&rep.hir, states, bytes)?;
                             states[idx] = State::new(&[], &[idx + 1, states.len()]);
                         }
                     }
                 },
             }
         }
-        HirKind::WordBoundary(_) => {
-            return Err(Error::UnsupportedFeature("word boundary assertions"));
+        HirKind::WordBoundary(boundary) => {
+            let look = match boundary {
+                hir::WordBoundary::Unicode | hir::WordBoundary::UnicodeNegate if bytes => {
+                    return Err(Error::UnsupportedFeature(
+                        "Unicode word boundaries in byte patterns",
+                    ));
+                }
+                hir::WordBoundary::Unicode => Look::WordBoundary,
+                hir::WordBoundary::UnicodeNegate => Look::NotWordBoundary,
+                hir::WordBoundary::Ascii => Look::WordBoundaryAscii,
+                hir::WordBoundary::AsciiNegate => Look::NotWordBoundaryAscii,
+            };
+            states.push(State::look(look, states.len() + 1));
         }
     }
 
     Ok(())
 }
 
+#[cfg(feature = "regex-syntax")]
+/// Compiles a class of chars into a trie of the UTF-8 byte sequences of its
+/// ranges, with one state per node. Every leaf leads to a final epsilon state
+/// so that the class has a single exit.
+fn compile_utf8_class(class: &hir::ClassUnicode, states: &mut Vec<State>) {
+    let mut nodes: Vec<Vec<(u8, u8, Option<usize>)>> = vec![vec![]];
+    for range in class.iter() {
+        for seq in Utf8Sequences::new(range.start(), range.end()) {
+            let mut node = 0;
+            for (i, r) in seq.as_slice().iter().enumerate() {
+                if i == seq.as_slice().len() - 1 {
+                    nodes[node].push((r.start, r.end, None));
+                    break;
+                }
+
+                let existing = nodes[node]
+                    .iter()
+                    .find(|(lo, hi, _)| (*lo, *hi) == (r.start, r.end))
+                    .and_then(|(_, _, child)| *child);
+                node = match existing {
+                    Some(child) => child,
+                    None => {
+                        let child = nodes.len();
+                        nodes.push(vec![]);
+                        nodes[node].push((r.start, r.end, Some(child)));
+                        child
+                    }
+                };
+            }
+        }
+    }
+
+    let base = states.len();
+    let exit = base + nodes.len();
+    for node in nodes {
+        let transitions = node
+            .iter()
+            .map(|&(lo, hi, child)| {
+                let to = child.map(|c| base + c).unwrap_or(exit);
+                Transition::ranges(&[(char::from(lo), char::from(hi))], to)
+            })
+            .collect::<Vec<_>>();
+        states.push(State::new(&transitions, &[]));
+    }
+    states.push(State::new(&[], &[exit + 1]));
+}
+
+#[cfg(feature = "regex-syntax")]
+fn push_byte_ranges(states: &mut Vec<State>, ranges: &[(u8, u8)]) {
+    let ranges = ranges
+        .iter()
+        .map(|&(lo, hi)| (char::from(lo), char::from(hi)))
+        .collect::<Vec<_>>();
+
+    states.push(State::new(
+        &[Transition::ranges(&ranges, states.len() + 1)],
+        &[],
+    ));
+}
+
+#[cfg(feature = "regex-syntax")]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     #[cfg(test)]
     use super::{compile, NFA};
@@ -148,7 +372,6 @@ mod tests {
     #[cfg(test)]
     fn matches(nfa: &NFA, s: &str) -> bool {
         let mut state = nfa.execution_state();
-        nfa.initialize_states(&mut state.current);
 
         nfa.matches(s, &mut state) == Some(s.len())
     }
@@ -184,6 +407,20 @@ mod tests {
         assert_eq!(matches(&nfa, "aaaaaaa"), true);
     }
 
+    #[test]
+    fn nested_rep() {
+        let nfa = compile("(a*)*b").unwrap();
+
+        assert_eq!(matches(&nfa, "b"), true);
+        assert_eq!(matches(&nfa, "aaab"), true);
+        assert_eq!(matches(&nfa, "aaa"), false);
+
+        let nfa = compile(r"(a?|\b)*(b*)+").unwrap();
+
+        assert_eq!(matches(&nfa, ""), true);
+        assert_eq!(matches(&nfa, "aabb"), true);
+    }
+
     #[test]
     fn a_zero_or_one() {
         let nfa = compile("a?").unwrap();
@@ -244,6 +481,14 @@ mod tests {
         assert_eq!(matches(&nfa, ""), false);
     }
 
+    #[test]
+    fn ascii_byte_class() {
+        let nfa = compile("(?-u:[a-c])+").unwrap();
+
+        assert_eq!(matches(&nfa, "abc"), true);
+        assert_eq!(matches(&nfa, "abd"), false);
+    }
+
     #[test]
     fn group() {
         let nfa = compile("(ab)a").unwrap();
@@ -278,6 +523,18 @@ mod tests {
         assert_eq!(matches(&nfa, "aaaaa"), true);
         assert_eq!(matches(&nfa, "aaaaaa"), true);
         assert_eq!(matches(&nfa, "aa"), false);
+
+        let nfa = compile("(ab){2,}").unwrap();
+
+        assert_eq!(matches(&nfa, "abab"), true);
+        assert_eq!(matches(&nfa, "ababab"), true);
+        assert_eq!(matches(&nfa, "abb"), false);
+        assert_eq!(matches(&nfa, "ab"), false);
+
+        let nfa = compile("(ab){0,}").unwrap();
+
+        assert_eq!(matches(&nfa, ""), true);
+        assert_eq!(matches(&nfa, "abab"), true);
     }
 
     #[test]
@@ -0,0 +1,90 @@
+use hashbrown::HashMap;
+
+use super::{CompileError, Context, State, StateID, Transition, NFA};
+
+impl NFA {
+    /// Builds an NFA matching the strings this one matches, except for those
+    /// that extend a shorter match. Matches then end at the first accept
+    /// state reached, like `*?` in a backtracking regex engine.
+    ///
+    /// The states of the new NFA are those of a DFA built by subset
+    /// construction, with every transition out of accept states removed.
+    pub fn shortest(&self) -> Result<NFA, CompileError> {
+        if self.contexts.is_some() {
+            return Err(CompileError::UnsupportedFeature(
+                "assertions in shortest-match patterns",
+            ));
+        }
+
+        let mut set = self.states();
+        self.initialize_states(&mut set, Context::Other);
+
+        let start = set.ones().collect::<Vec<_>>();
+        let mut ids: HashMap<Vec<StateID>, StateID> = HashMap::new();
+        ids.insert(start.clone(), 0);
+        let mut sets = vec![start];
+
+        let mut states = vec![];
+        while states.len() < sets.len() {
+            let current = sets[states.len()].clone();
+            if current.iter().any(|&i| self.states[i].accept.is_some()) {
+                states.push(State::accept(&[], &[]));
+                continue;
+            }
+
+            let mut transitions: Vec<Transition> = vec![];
+            for class in 1..self.alphabet.len() {
+                self.step_class(current.iter().copied(), class, &mut set);
+                if set.count_ones(..) == 0 {
+                    continue;
+                }
+
+                let next = set.ones().collect::<Vec<_>>();
+                let to = match ids.get(&next) {
+                    Some(id) => *id,
+                    None => {
+                        let id = sets.len();
+                        ids.insert(next.clone(), id);
+                        sets.push(next);
+                        id
+                    }
+                };
+
+                let ranges = self.alphabet.ranges(class);
+                match transitions.iter_mut().find(|t| t.to == to) {
+                    Some(t) => t.ranges.extend(ranges),
+                    None => transitions.push(Transition::ranges(&ranges, to)),
+                }
+            }
+
+            states.push(State::new(&transitions, &[]));
+        }
+
+        Ok(NFA::new(states))
+    }
+}
+
+#[cfg(all(test, feature = "regex-syntax"))]
+mod tests {
+    use crate::nfa::NFA;
+
+    fn matches(nfa: &NFA, s: &str) -> bool {
+        let mut state = nfa.execution_state();
+        nfa.matches(s, &mut state) == Some(s.len())
+    }
+
+    #[test]
+    fn shortest() {
+        let nfa = NFA::from_regex(r"/\*.*\*/").unwrap().shortest().unwrap();
+        assert!(matches(&nfa, "/* a */"));
+        assert!(matches(&nfa, "/**/"));
+        assert!(!matches(&nfa, "/* a */ b */"));
+        assert!(!matches(&nfa, "/* a"));
+
+        let nfa = NFA::from_regex("a+").unwrap().shortest().unwrap();
+        assert!(matches(&nfa, "a"));
+        assert!(!matches(&nfa, "aa"));
+
+        assert!(NFA::from_regex(r"a\b").unwrap().shortest().is_err());
+    }
+}
@@ -0,0 +1,855 @@
+//! A binary format for compiled lexicons, so that they can be shipped as data
+//! and loaded without compiling any patterns:
+//!
+//! ```ignore
+//! // Ahead of time
+//! std::fs::write("lexicon.bin", lexicon.to_bytes())?;
+//!
+//! // At startup
+//! static BYTES: &Aligned<[u8]> = &Aligned(*include_bytes!("lexicon.bin"));
+//! let lexicon = Lexicon::from_static_bytes(&BYTES.0)?;
+//!
+//! #[repr(align(4))]
+//! struct Aligned<T: ?Sized>(T);
+//! ```
+//!
+//! All integers are little-endian, and everything read is checked, so corrupt
+//! data is an error rather than a panic while lexing. A lexicon loaded from
+//! `&'static [u8]` borrows the transition tables of its DFAs, its bulk, when
+//! the data is 4-byte aligned and the target is little-endian. Otherwise they
+//! are copied.
+
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::Arc;
+
+use hashbrown::HashSet;
+
+use crate::dfa::{DEAD_ID, DFA};
+use crate::lexicon::{Action, Automaton, Lexicon, Mode, Rule, Stats};
+use crate::nfa::{Alphabet, CharRange, Context, Look, Prefixes, State, Transition, NFA};
+
+const MAGIC: &[u8; 4] = b"LEXI";
+const VERSION: u32 = 1;
+
+/// Stands in for missing ids, states and rules.
+const NONE: u32 = u32::MAX;
+
+const LOOKS: [Look; 8] = [
+    Look::StartLine,
+    Look::EndLine,
+    Look::StartText,
+    Look::EndText,
+    Look::WordBoundary,
+    Look::NotWordBoundary,
+    Look::WordBoundaryAscii,
+    Look::NotWordBoundaryAscii,
+];
+
+/// Why serialized data could not be loaded as a lexicon.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The data does not start with the magic bytes of a lexicon.
+    NotALexicon,
+    /// The data was written by another version of the format.
+    UnsupportedVersion(u32),
+    /// The data ends before the lexicon does.
+    Truncated,
+    /// The data holds a value that is out of range, such as a state id past
+    /// the last state, in the part named.
+    Invalid(&'static str),
+    /// No kind was given for the id of a rule.
+    UnknownKind(u64),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NotALexicon => write!(f, "not a serialized lexicon"),
+            Error::UnsupportedVersion(v) => write!(f, "unsupported format version {}", v),
+            Error::Truncated => write!(f, "truncated lexicon"),
+            Error::Invalid(part) => write!(f, "invalid {}", part),
+            Error::UnknownKind(id) => write!(f, "unknown token kind {}", id),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl<K: Copy> Lexicon<K> {
+    /// Serializes the lexicon, writing the kind of each rule as the id
+    /// returned by `id`.
+    pub fn to_bytes_with(&self, id: impl Fn(K) -> u64) -> Vec<u8> {
+        let mut w = Writer { out: vec![] };
+        w.out.extend_from_slice(MAGIC);
+        w.u32(VERSION);
+
+        w.u64(self.stats.nfa_states as u64);
+        w.u64(self.stats.dfa_states as u64);
+        w.u64(self.stats.minimized_dfa_states as u64);
+
+        let mut ignore_chars = self.ignore_chars.iter().copied().collect::<Vec<_>>();
+        ignore_chars.sort_unstable();
+        w.len(ignore_chars.len());
+        for c in ignore_chars {
+            w.u32(c as u32);
+        }
+
+        w.len(self.rules.len());
+        for rule in self.rules.iter() {
+            match rule.kind {
+                Some(kind) => {
+                    w.u8(1);
+                    w.u64(id(kind));
+                }
+                None => w.u8(0),
+            }
+            w.u8(rule.precedence);
+            match rule.action {
+                None => w.u8(0),
+                Some(Action::Push(mode)) => {
+                    w.u8(1);
+                    w.len(mode);
+                }
+                Some(Action::Pop) => w.u8(2),
+                Some(Action::Switch(mode)) => {
+                    w.u8(3);
+                    w.len(mode);
+                }
+            }
+            w.optional_nfa(&rule.trailing_context);
+            w.optional_nfa(&rule.captures);
+        }
+
+        w.len(self.modes.len());
+        for mode in self.modes.iter() {
+            w.len(mode.rules.len());
+            for &rule in mode.rules.iter() {
+                w.len(rule);
+            }
+
+            match &mode.automaton {
+                Automaton::Dfa(dfa) => {
+                    w.u8(0);
+                    w.dfa(dfa);
+                }
+                Automaton::Lazy {
+                    nfa,
+                    prefixes,
+                    cache_size,
+                } => {
+                    w.u8(1);
+                    w.u64(*cache_size as u64);
+                    w.nfa(nfa);
+                    w.prefixes(prefixes);
+                }
+                Automaton::PikeVM { nfa, prefixes } => {
+                    w.u8(2);
+                    w.nfa(nfa);
+                    w.prefixes(prefixes);
+                }
+            }
+        }
+
+        w.out
+    }
+
+    /// Loads a lexicon written by `to_bytes_with`, turning the id of each
+    /// rule back into its kind with `kind`.
+    pub fn from_bytes_with(bytes: &[u8], kind: impl Fn(u64) -> Option<K>) -> Result<Self, Error> {
+        let mut r = Reader {
+            bytes,
+            pos: 0,
+            keep: |table| Cow::Owned(table.into_owned()),
+        };
+        r.lexicon(kind)
+    }
+
+    /// Like `from_bytes_with`, but borrows the transition tables from `bytes`
+    /// where it can rather than copying them.
+    pub fn from_static_bytes_with(
+        bytes: &'static [u8],
+        kind: impl Fn(u64) -> Option<K>,
+    ) -> Result<Self, Error> {
+        let mut r = Reader {
+            bytes,
+            pos: 0,
+            keep: |table| table,
+        };
+        r.lexicon(kind)
+    }
+}
+
+impl Lexicon {
+    /// Serializes the lexicon, to be loaded again with `from_bytes` or
+    /// `from_static_bytes`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_bytes_with(|kind| kind as u64)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        Self::from_bytes_with(bytes, |id| usize::try_from(id).ok())
+    }
+
+    pub fn from_static_bytes(bytes: &'static [u8]) -> Result<Self, Error> {
+        Self::from_static_bytes_with(bytes, |id| usize::try_from(id).ok())
+    }
+}
+
+struct Writer {
+    out: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.out.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.out.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.out.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Writes a length or index, which is always far below `u32::MAX`.
+    fn len(&mut self, value: usize) {
+        self.u32(value as u32);
+    }
+
+    fn id(&mut self, value: Option<usize>) {
+        self.u32(value.map_or(NONE, |v| v as u32));
+    }
+
+    fn ranges(&mut self, ranges: &[CharRange]) {
+        self.len(ranges.len());
+        for &(lo, hi) in ranges.iter() {
+            self.u32(lo as u32);
+            self.u32(hi as u32);
+        }
+    }
+
+    fn optional_nfa(&mut self, nfa: &Option<NFA>) {
+        match nfa {
+            Some(nfa) => {
+                self.u8(1);
+                self.nfa(nfa);
+            }
+            None => self.u8(0),
+        }
+    }
+
+    /// Writes the states and capture names of an NFA, from which
+    /// `NFA::new` rebuilds the rest.
+    fn nfa(&mut self, nfa: &NFA) {
+        self.len(nfa.capture_names.len());
+        for name in nfa.capture_names.iter() {
+            match name {
+                Some(name) => {
+                    self.u8(1);
+                    self.len(name.len());
+                    self.out.extend_from_slice(name.as_bytes());
+                }
+                None => self.u8(0),
+            }
+        }
+
+        self.len(nfa.states.len());
+        for state in nfa.states.iter() {
+            self.id(state.accept);
+            let look = state.look.map(|l| LOOKS.iter().position(|&x| x == l));
+            self.u8(look.flatten().map_or(u8::MAX, |l| l as u8));
+            self.u8(state.cut as u8);
+            self.id(state.capture);
+
+            self.len(state.transitions.len());
+            for t in state.transitions.iter() {
+                self.ranges(&t.ranges);
+                self.len(t.to);
+            }
+            self.len(state.epsilon_transitions.len());
+            for &to in state.epsilon_transitions.iter() {
+                self.len(to);
+            }
+        }
+    }
+
+    fn prefixes(&mut self, prefixes: &Prefixes) {
+        for ranges in prefixes.starting_ranges() {
+            self.ranges(&ranges);
+        }
+    }
+
+    /// Writes the tables of a DFA, with its transitions last and aligned to 4
+    /// bytes so that they can be borrowed.
+    fn dfa(&mut self, dfa: &DFA) {
+        let alphabet = &dfa.alphabet;
+        self.len(alphabet.len());
+        for &class in alphabet.ascii.iter() {
+            self.u32(class);
+        }
+        self.len(alphabet.ranges.len());
+        for &(lo, hi, class) in alphabet.ranges.iter() {
+            self.u32(lo as u32);
+            self.u32(hi as u32);
+            self.u32(class);
+        }
+
+        self.len(dfa.starts.len());
+        self.len(dfa.state_count());
+        for &context in dfa.contexts.iter() {
+            self.len(context);
+        }
+        for &start in dfa.starts.iter() {
+            self.len(start);
+        }
+        for &accept in dfa.accepts.iter() {
+            self.id(accept);
+        }
+
+        while !self.out.len().is_multiple_of(4) {
+            self.u8(0);
+        }
+        for &to in dfa.transitions.iter() {
+            self.u32(to);
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    /// Keeps a table borrowed from `bytes` for a DFA, copying it unless
+    /// `bytes` is static.
+    keep: fn(Cow<'a, [u32]>) -> Cow<'static, [u32]>,
+}
+
+impl<'a> Reader<'a> {
+    fn lexicon<K>(&mut self, kind: impl Fn(u64) -> Option<K>) -> Result<Lexicon<K>, Error> {
+        if self.take(4).map_err(|_| Error::NotALexicon)? != MAGIC {
+            return Err(Error::NotALexicon);
+        }
+        let version = self.u32()?;
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        let stats = Stats {
+            nfa_states: self.u64()? as usize,
+            dfa_states: self.u64()? as usize,
+            minimized_dfa_states: self.u64()? as usize,
+        };
+
+        let mut ignore_chars = HashSet::new();
+        for _ in 0..self.u32()? {
+            ignore_chars.insert(self.char()?);
+        }
+
+        let mut rules = vec![];
+        for _ in 0..self.u32()? {
+            let kind = match self.u8()? {
+                0 => None,
+                1 => {
+                    let id = self.u64()?;
+                    Some(kind(id).ok_or(Error::UnknownKind(id))?)
+                }
+                _ => return Err(Error::Invalid("rule")),
+            };
+            let precedence = self.u8()?;
+            let action = match self.u8()? {
+                0 => None,
+                1 => Some(Action::Push(self.len()?)),
+                2 => Some(Action::Pop),
+                3 => Some(Action::Switch(self.len()?)),
+                _ => return Err(Error::Invalid("action")),
+            };
+
+            let trailing_context = self.optional_nfa()?;
+            let captures = self.optional_nfa()?;
+            // Only the NFAs of captures are run with slots for their groups
+            let slots_valid = captures.iter().all(|nfa| {
+                let slots = 2 * nfa.capture_count();
+                nfa.states
+                    .iter()
+                    .all(|s| s.capture.is_none_or(|c| c < slots))
+            });
+            if !slots_valid {
+                return Err(Error::Invalid("NFA state"));
+            }
+
+            rules.push(Rule {
+                kind,
+                precedence,
+                action,
+                trailing_context,
+                captures,
+            });
+        }
+
+        let mut modes = vec![];
+        for _ in 0..self.u32()? {
+            let mut indexes = vec![];
+            for _ in 0..self.u32()? {
+                indexes.push(self.index(rules.len(), "mode")?);
+            }
+
+            let automaton = match self.u8()? {
+                0 => Automaton::Dfa(Arc::new(self.dfa(indexes.len())?)),
+                1 => Automaton::Lazy {
+                    cache_size: self.u64()? as usize,
+                    nfa: self.nfa(indexes.len())?,
+                    prefixes: self.prefixes(indexes.len())?,
+                },
+                2 => Automaton::PikeVM {
+                    nfa: self.nfa(indexes.len())?,
+                    prefixes: self.prefixes(indexes.len())?,
+                },
+                _ => return Err(Error::Invalid("mode")),
+            };
+
+            modes.push(Mode {
+                rules: indexes,
+                automaton,
+            });
+        }
+
+        if self.pos != self.bytes.len() {
+            return Err(Error::Invalid("lexicon"));
+        }
+        let actions_valid = rules.iter().all(|rule| match rule.action {
+            Some(Action::Push(mode)) | Some(Action::Switch(mode)) => mode < modes.len(),
+            _ => true,
+        });
+        if modes.is_empty() || !actions_valid {
+            return Err(Error::Invalid("mode"));
+        }
+
+        Ok(Lexicon {
+            ignore_chars: Arc::new(ignore_chars),
+            rules: Arc::new(rules),
+            modes: Arc::new(modes),
+            stats,
+            loaded: true,
+        })
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let end = self.pos.checked_add(len).ok_or(Error::Truncated)?;
+        let bytes = self.bytes.get(self.pos..end).ok_or(Error::Truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn len(&mut self) -> Result<usize, Error> {
+        Ok(self.u32()? as usize)
+    }
+
+    /// Reads an index, which must be below `len`.
+    fn index(&mut self, len: usize, part: &'static str) -> Result<usize, Error> {
+        match self.len()? {
+            i if i < len => Ok(i),
+            _ => Err(Error::Invalid(part)),
+        }
+    }
+
+    /// Reads an index below `len`, or `None`.
+    fn id(&mut self, len: usize, part: &'static str) -> Result<Option<usize>, Error> {
+        match self.u32()? {
+            NONE => Ok(None),
+            i if (i as usize) < len => Ok(Some(i as usize)),
+            _ => Err(Error::Invalid(part)),
+        }
+    }
+
+    fn char(&mut self) -> Result<char, Error> {
+        char::from_u32(self.u32()?).ok_or(Error::Invalid("char"))
+    }
+
+    fn ranges(&mut self) -> Result<Vec<CharRange>, Error> {
+        let mut ranges = vec![];
+        for _ in 0..self.u32()? {
+            let (lo, hi) = (self.char()?, self.char()?);
+            if lo > hi {
+                return Err(Error::Invalid("char range"));
+            }
+            ranges.push((lo, hi));
+        }
+
+        Ok(ranges)
+    }
+
+    fn optional_nfa(&mut self) -> Result<Option<NFA>, Error> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => self.nfa(1).map(Some),
+            _ => Err(Error::Invalid("rule")),
+        }
+    }
+
+    /// Reads an NFA whose accept states accept one of `rules` rules.
+    fn nfa(&mut self, rules: usize) -> Result<NFA, Error> {
+        let mut capture_names = vec![];
+        for _ in 0..self.u32()? {
+            capture_names.push(match self.u8()? {
+                0 => None,
+                1 => {
+                    let len = self.len()?;
+                    let name = std::str::from_utf8(self.take(len)?);
+                    Some(name.map_err(|_| Error::Invalid("capture name"))?.into())
+                }
+                _ => return Err(Error::Invalid("capture name")),
+            });
+        }
+
+        let mut states = vec![];
+        let mut targets = vec![];
+        for _ in 0..self.u32()? {
+            let accept = self.id(rules, "NFA state")?;
+            let look = match self.u8()? {
+                u8::MAX => None,
+                l => Some(*LOOKS.get(l as usize).ok_or(Error::Invalid("NFA state"))?),
+            };
+            let cut = match self.u8()? {
+                0 => false,
+                1 => true,
+                _ => return Err(Error::Invalid("NFA state")),
+            };
+            let capture = self.id(NONE as usize, "NFA state")?;
+
+            let mut transitions = vec![];
+            for _ in 0..self.u32()? {
+                let ranges = self.ranges()?;
+                let to = self.len()?;
+                targets.push(to);
+                transitions.push(Transition::ranges(&ranges, to));
+            }
+            let mut epsilon_transitions = vec![];
+            for _ in 0..self.u32()? {
+                let to = self.len()?;
+                targets.push(to);
+                epsilon_transitions.push(to);
+            }
+
+            states.push(State {
+                accept,
+                look,
+                cut,
+                capture,
+                transitions,
+                epsilon_transitions,
+            });
+        }
+
+        if states.is_empty() || targets.iter().any(|&to| to >= states.len()) {
+            return Err(Error::Invalid("NFA state"));
+        }
+
+        let mut nfa = NFA::new(states);
+        nfa.capture_names = Arc::new(capture_names);
+        Ok(nfa)
+    }
+
+    fn prefixes(&mut self, rules: usize) -> Result<Arc<Prefixes>, Error> {
+        let starting_ranges = (0..rules)
+            .map(|_| self.ranges())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Arc::new(Prefixes::new(&starting_ranges)))
+    }
+
+    /// Reads a DFA whose states accept one of `rules` rules.
+    fn dfa(&mut self, rules: usize) -> Result<DFA, Error> {
+        let classes = self.len()?;
+        let mut ascii = [0; 128];
+        for class in ascii.iter_mut() {
+            *class = self.index(classes, "alphabet")? as u32;
+        }
+        let mut ranges = vec![];
+        for _ in 0..self.u32()? {
+            let (lo, hi) = (self.char()?, self.char()?);
+            let class = self.index(classes, "alphabet")? as u32;
+
+            // Ranges must be sorted, apart, and outside of ASCII
+            let min = ranges.last().map_or(0x80, |&(_, last, _)| last as u32 + 1);
+            if lo > hi || (lo as u32) < min {
+                return Err(Error::Invalid("alphabet"));
+            }
+            ranges.push((lo, hi, class));
+        }
+        if classes == 0 {
+            return Err(Error::Invalid("alphabet"));
+        }
+
+        let rows = self.len()?;
+        if rows != 1 && rows != Context::ALL.len() {
+            return Err(Error::Invalid("DFA"));
+        }
+        let states = self.len()?;
+        let contexts = (0..classes)
+            .map(|_| self.index(rows, "DFA"))
+            .collect::<Result<Vec<_>, _>>()?;
+        let starts = (0..rows)
+            .map(|_| self.index(states, "DFA"))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut accepts = vec![];
+        for _ in 0..states.checked_mul(rows).ok_or(Error::Invalid("DFA"))? {
+            accepts.push(self.id(rules, "DFA")?);
+        }
+
+        self.take((4 - self.pos % 4) % 4)?;
+        let len = states.checked_mul(classes).ok_or(Error::Invalid("DFA"))?;
+        let table = self.take(len.checked_mul(4).ok_or(Error::Truncated)?)?;
+        let transitions = match cast(table) {
+            Some(table) => Cow::Borrowed(table),
+            None => Cow::Owned(
+                table
+                    .chunks_exact(4)
+                    .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                    .collect(),
+            ),
+        };
+        if transitions
+            .iter()
+            .any(|&to| to != DEAD_ID && to as usize >= states)
+        {
+            return Err(Error::Invalid("DFA"));
+        }
+
+        Ok(DFA {
+            alphabet: Arc::new(Alphabet {
+                ascii,
+                ranges,
+                classes,
+            }),
+            contexts,
+            starts,
+            transitions: (self.keep)(transitions),
+            accepts,
+        })
+    }
+}
+
+/// Views little-endian bytes as a table of `u32`s, if they are aligned and
+/// the target is little-endian.
+fn cast(bytes: &[u8]) -> Option<&[u32]> {
+    if cfg!(target_endian = "big") {
+        return None;
+    }
+
+    // SAFETY: every bit pattern is a valid `u32`.
+    match unsafe { bytes.align_to::<u32>() } {
+        ([], table, []) => Some(table),
+        _ => None,
+    }
+}
+
+#[cfg(all(test, feature = "regex-syntax"))]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::Error;
+    use crate::lexicon::Automaton;
+    use crate::*;
+
+    fn lexicon() -> Lexicon {
+        LexiconBuilder::new()
+            .ignore_chars(" \n")
+            .pattern(1, "[a-z]+")
+            .literal(2, "if")
+            .pattern(3, r"(?P<digits>[0-9]+)\b")
+            .skip("#[^\n]*")
+            .pattern_with_lookahead(4, "[0-9]+", r"\.\.")
+            .literal(5, "\"")
+            .action(Action::Push(1))
+            .modes(&[1])
+            .pattern(6, "[^\"]+")
+            .literal(7, "\"")
+            .action(Action::Pop)
+            .build()
+            .unwrap()
+    }
+
+    /// A lexicon small enough to corrupt each of its bytes in turn.
+    fn small() -> LexiconBuilder {
+        LexiconBuilder::new()
+            .pattern(1, "[a-c]+")
+            .pattern(2, "(?P<n>[0-9])")
+            .pattern_with_lookahead(3, "[0-9]", "x")
+            .literal(4, "(")
+            .action(Action::Push(1))
+            .modes(&[1])
+            .literal(5, ")")
+            .action(Action::Pop)
+    }
+
+    fn tokens<K: Copy>(lexicon: &Lexicon<K>, input: &str) -> Vec<(Option<K>, String)> {
+        Lexer::new(lexicon, input)
+            .map(|next| match next {
+                Next::Token(t) => (Some(t.kind), t.text.to_string()),
+                Next::Error(_, span) => (None, input[span.range()].to_string()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let input = "if iffy 12 # comment\n3..4 \"a b\" é";
+        let builders = vec![
+            lexicon(),
+            LexiconBuilder::new()
+                .ignore_chars(" ")
+                .pattern(1, r"\w+")
+                .pattern(2, r"(?P<n>[0-9]+)\.\.")
+                .lazy_dfa(1 << 16)
+                .build()
+                .unwrap(),
+            LexiconBuilder::new()
+                .pattern(1, r"[a-z]+\b")
+                .literal(2, " ")
+                .pattern(3, r"(x*)*y")
+                .pike_vm()
+                .build()
+                .unwrap(),
+        ];
+
+        for lexicon in builders {
+            let loaded = Lexicon::from_bytes(&lexicon.to_bytes()).unwrap();
+
+            assert_eq!(tokens(&loaded, input), tokens(&lexicon, input));
+            assert_eq!(loaded.stats(), lexicon.stats());
+            assert_eq!(loaded.to_bytes(), lexicon.to_bytes());
+        }
+
+        let lexicon = lexicon();
+        let loaded = Lexicon::from_bytes(&lexicon.to_bytes()).unwrap();
+        let mut lexer = Lexer::new(&loaded, "42");
+        lexer.next();
+        assert_eq!(lexer.captures().unwrap().name("digits"), Some("42"));
+    }
+
+    #[test]
+    fn kinds() {
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        enum Kind {
+            Word,
+            Number,
+        }
+
+        let lexicon = LexiconBuilder::new()
+            .pattern(Kind::Word, "[a-z]+")
+            .pattern(Kind::Number, "[0-9]+")
+            .build()
+            .unwrap();
+        let bytes = lexicon.to_bytes_with(|k| k as u64);
+
+        let loaded = Lexicon::from_bytes_with(&bytes, |id| match id {
+            0 => Some(Kind::Word),
+            1 => Some(Kind::Number),
+            _ => None,
+        })
+        .unwrap();
+        assert_eq!(tokens(&loaded, "ab12"), tokens(&lexicon, "ab12"));
+
+        let unknown = Lexicon::from_bytes_with(&bytes, |id| Some(id).filter(|&id| id == 0));
+        assert_eq!(unknown.err(), Some(Error::UnknownKind(1)));
+    }
+
+    #[test]
+    fn static_bytes() {
+        let bytes = lexicon().to_bytes();
+
+        // Copy into a buffer of `u32`s so that the bytes are aligned
+        let words = Vec::leak(vec![0u32; bytes.len().div_ceil(4)]);
+        let aligned = unsafe { words.align_to_mut::<u8>().1 };
+        aligned[..bytes.len()].copy_from_slice(&bytes);
+        let aligned = &aligned[..bytes.len()];
+
+        let loaded = Lexicon::from_static_bytes(aligned).unwrap();
+        let input = "if 12 \"a\"";
+        assert_eq!(tokens(&loaded, input), tokens(&lexicon(), input));
+
+        let borrowed = loaded.modes.iter().all(|mode| match &mode.automaton {
+            Automaton::Dfa(dfa) => matches!(dfa.transitions, Cow::Borrowed(_)),
+            _ => false,
+        });
+        assert_eq!(borrowed, cfg!(target_endian = "little"));
+    }
+
+    #[test]
+    fn corrupt() {
+        let bytes = small().build().unwrap().to_bytes();
+
+        assert_eq!(Lexicon::from_bytes(b"LEX").err(), Some(Error::NotALexicon));
+        assert_eq!(
+            Lexicon::from_bytes(b"JSON{}").err(),
+            Some(Error::NotALexicon)
+        );
+
+        let mut version = bytes.clone();
+        version[4] = 9;
+        assert_eq!(
+            Lexicon::from_bytes(&version).err(),
+            Some(Error::UnsupportedVersion(9))
+        );
+
+        for len in 4..bytes.len() {
+            assert_eq!(
+                Lexicon::from_bytes(&bytes[..len]).err(),
+                Some(Error::Truncated)
+            );
+        }
+
+        let mut trailing = bytes;
+        trailing.push(0);
+        assert_eq!(
+            Lexicon::from_bytes(&trailing).err(),
+            Some(Error::Invalid("lexicon"))
+        );
+    }
+
+    #[test]
+    fn corrupt_bytes() {
+        let input = "ab1x2(c)$";
+
+        // Every corruption of a single byte must either load or be an error,
+        // and lexing with whatever loads must not panic
+        let lexicons = [
+            small().build(),
+            small().lazy_dfa(1 << 12).build(),
+            small().pike_vm().build(),
+        ];
+        for lexicon in lexicons.iter() {
+            let bytes = lexicon.as_ref().unwrap().to_bytes();
+            for i in 8..bytes.len() {
+                for value in [0x00, 0x01, 0x02, 0xFF].iter() {
+                    let mut corrupt = bytes.clone();
+                    corrupt[i] = *value;
+                    if let Ok(lexicon) = Lexicon::from_bytes(&corrupt) {
+                        let mut lexer = Lexer::new(&lexicon, input);
+                        while lexer.next().is_some() {
+                            lexer.captures();
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
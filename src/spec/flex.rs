@@ -0,0 +1,1132 @@
+//! Imports the rules of a flex grammar into a `LexiconBuilder`:
+//!
+//! ```
+//! let import = lexer::spec::flex::import(r#"
+//! DIGIT    [0-9]
+//! %x STRING
+//! %%
+//! {DIGIT}+      return NUMBER;
+//! \"            BEGIN(STRING);
+//! <STRING>[^"]+ return TEXT;
+//! <STRING>\"    BEGIN(INITIAL);
+//! [ \t\n]+      ;
+//! "#)
+//! .unwrap();
+//!
+//! assert_eq!(import.kinds, vec!["NUMBER", "TEXT"]);
+//! # #[cfg(feature = "regex-syntax")]
+//! let lexicon = import.build().unwrap();
+//! ```
+//!
+//! Named definitions, start conditions (`%s`, `%x`, `<SC>` prefixes and
+//! `<SC>{ }` scopes), trailing context and `|` actions are supported. Actions
+//! are only read for the token they `return` and how they change the start
+//! condition, with `BEGIN`, `yy_push_state` or `yy_pop_state`, and rules that
+//! return nothing become skip rules. Anything else, such as other C code, is
+//! left out with a `Warning` giving its line.
+//!
+//! Rules keep the precedence they have in flex: the longest match wins,
+//! followed by the rule that comes first. Patterns are only compiled when the
+//! lexicon is built, and `Import::build` gives their errors the line of the
+//! rule they are in.
+
+use std::fmt;
+use std::iter::Peekable;
+use std::mem;
+use std::str::CharIndices;
+
+use hashbrown::HashMap;
+
+use crate::lexicon::{Action, Error as LexiconError, Lexicon, LexiconBuilder, ModeID, INITIAL};
+
+/// The rules of a grammar and what could not be imported.
+pub struct Import {
+    /// The rules of the grammar, where the kind of each token is its index in
+    /// `kinds`.
+    pub builder: LexiconBuilder,
+    /// The values returned by actions, such as `NUMBER` or `'+'`.
+    pub kinds: Vec<String>,
+    /// The names of the start conditions, with `INITIAL` first, by mode.
+    pub modes: Vec<String>,
+    /// The line of each rule of `builder`, by index.
+    pub lines: Vec<usize>,
+    pub warnings: Vec<Warning>,
+}
+
+/// A part of a grammar that was left out, and the line it starts on.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Warning {
+    pub line: usize,
+    pub unsupported: Unsupported,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Unsupported {
+    /// C code outside of actions, such as a `%{ %}` block or an indented
+    /// line.
+    Code,
+    /// The statements of an action other than those returning a token or
+    /// changing the start condition.
+    Action(String),
+    Option(String),
+    Directive(String),
+    /// A `<<EOF>>` rule.
+    EndOfFile,
+    /// A rule with trailing context that returns nothing. Skip rules cannot
+    /// have trailing context, so the rule is left out.
+    SkippedTrailingContext,
+    /// The user code after the rules.
+    UserCode,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Error {
+    pub line: usize,
+    pub kind: ErrorKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ErrorKind {
+    /// The grammar has no `%%` line before its rules.
+    MissingRules,
+    /// A `{name}` in a pattern without a definition.
+    UndefinedName(String),
+    UndefinedStartCondition(String),
+    /// A quote, class, braces, action, comment, code block or list of start
+    /// conditions that is not closed.
+    Unterminated(&'static str),
+    /// A line that is not a valid definition or rule.
+    Syntax(&'static str),
+    /// A rule that could not be built by `Import::build`.
+    Lexicon(LexiconError),
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.unsupported {
+            Unsupported::Code => write!(f, "C code left out"),
+            Unsupported::Action(code) => write!(f, "action code left out: {}", code),
+            Unsupported::Option(option) => write!(f, "unsupported option {}", option),
+            Unsupported::Directive(directive) => write!(f, "unsupported {}", directive),
+            Unsupported::EndOfFile => write!(f, "<<EOF>> rules are unsupported"),
+            Unsupported::SkippedTrailingContext => {
+                write!(f, "rule with trailing context returning nothing left out")
+            }
+            Unsupported::UserCode => write!(f, "user code left out"),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            ErrorKind::MissingRules => write!(f, "missing %% before the rules"),
+            ErrorKind::UndefinedName(name) => write!(f, "undefined name {{{}}}", name),
+            ErrorKind::UndefinedStartCondition(name) => {
+                write!(f, "undefined start condition {}", name)
+            }
+            ErrorKind::Unterminated(what) => write!(f, "unterminated {}", what),
+            ErrorKind::Syntax(message) => write!(f, "{}", message),
+            ErrorKind::Lexicon(LexiconError::InvalidRegex(e)) => write!(f, "{}", e),
+            ErrorKind::Lexicon(LexiconError::EmptyLiteral(_)) => write!(f, "empty literal"),
+            ErrorKind::Lexicon(LexiconError::EmptyTrailingContextHead(_)) => {
+                write!(
+                    f,
+                    "pattern before the trailing context matches the empty string"
+                )
+            }
+            ErrorKind::Lexicon(LexiconError::EmptyShortestPattern(_)) => {
+                write!(f, "shortest-match pattern matches the empty string")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Import {
+    /// Builds the lexicon, with errors giving the line of the rule they are
+    /// in. Errors in rules added to `builder` after importing are at line 0.
+    pub fn build(self) -> Result<Lexicon, Error> {
+        let lines = self.lines;
+        self.builder.build_with(false).map_err(|(rule, e)| Error {
+            line: lines.get(rule).copied().unwrap_or(0),
+            kind: ErrorKind::Lexicon(e),
+        })
+    }
+}
+
+/// Options that only change the generated C code.
+const IGNORED_OPTIONS: [&str; 10] = [
+    "noyywrap",
+    "nounput",
+    "noinput",
+    "yylineno",
+    "stack",
+    "8bit",
+    "nodefault",
+    "warn",
+    "never-interactive",
+    "batch",
+];
+
+/// Imports the definitions and rules sections of a flex grammar.
+pub fn import(source: &str) -> Result<Import, Error> {
+    let mut parser = Parser {
+        lines: source.lines().collect(),
+        line: 0,
+        definitions: HashMap::new(),
+        conditions: vec![],
+        case_insensitive: false,
+        warnings: vec![],
+    };
+
+    parser.definitions()?;
+    let rules = parser.rules()?;
+    parser.user_code();
+
+    Ok(parser.build(rules))
+}
+
+struct Parser<'s> {
+    lines: Vec<&'s str>,
+    /// The number of lines read.
+    line: usize,
+    /// The pattern of each definition, translated and wrapped in a group.
+    definitions: HashMap<String, String>,
+    /// The name of each start condition after `INITIAL`, and whether it is
+    /// exclusive.
+    conditions: Vec<(String, bool)>,
+    case_insensitive: bool,
+    warnings: Vec<Warning>,
+}
+
+/// A rule with its action read, before it is added to the builder.
+struct Rule {
+    line: usize,
+    modes: Vec<ModeID>,
+    pattern: String,
+    trailing: Option<String>,
+    code: Code,
+}
+
+/// What an action does, as far as it can be imported.
+#[derive(Clone)]
+struct Code {
+    kind: Option<String>,
+    action: Option<Action>,
+}
+
+impl<'s> Parser<'s> {
+    fn next_line(&mut self) -> Option<&'s str> {
+        let line = self.lines.get(self.line).copied();
+        self.line += 1;
+        line
+    }
+
+    fn error(&self, line: usize, kind: ErrorKind) -> Error {
+        Error { line, kind }
+    }
+
+    fn warn(&mut self, line: usize, unsupported: Unsupported) {
+        self.warnings.push(Warning { line, unsupported });
+    }
+
+    fn definitions(&mut self) -> Result<(), Error> {
+        loop {
+            let text = match self.next_line() {
+                Some(text) => text,
+                None => return Err(self.error(self.lines.len(), ErrorKind::MissingRules)),
+            };
+            let line = self.line;
+
+            if text.trim_end() == "%%" {
+                return Ok(());
+            }
+            if text.trim().is_empty() || self.skip_comment(text)? {
+                continue;
+            }
+            if text.starts_with("%{") {
+                self.code_block()?;
+                continue;
+            }
+            if text.starts_with(char::is_whitespace) {
+                self.warn(line, Unsupported::Code);
+                continue;
+            }
+
+            if let Some(directive) = text.strip_prefix('%') {
+                let mut words = directive.split_whitespace();
+                match words.next() {
+                    Some("s") | Some("S") => {
+                        let names = words.map(|name| (name.to_string(), false));
+                        self.conditions.extend(names);
+                    }
+                    Some("x") | Some("X") => {
+                        let names = words.map(|name| (name.to_string(), true));
+                        self.conditions.extend(names);
+                    }
+                    Some("option") => {
+                        for option in words {
+                            self.option(line, option);
+                        }
+                    }
+                    Some("pointer") | Some("array") => {}
+                    _ => self.warn(line, Unsupported::Directive(text.trim().into())),
+                }
+                continue;
+            }
+
+            let end = text.find(char::is_whitespace).unwrap_or(text.len());
+            let (name, pattern) = text.split_at(end);
+            let (pattern, trailing, rest) = self.pattern(line, pattern.trim_start())?;
+            if trailing.is_some() {
+                return Err(self.error(line, ErrorKind::Syntax("trailing context in a definition")));
+            }
+            if !rest.trim().is_empty() && !is_comment(rest.trim()) {
+                return Err(self.error(line, ErrorKind::Syntax("text after a definition")));
+            }
+
+            self.definitions
+                .insert(name.into(), format!("(?:{})", pattern));
+        }
+    }
+
+    fn option(&mut self, line: usize, option: &str) {
+        match option {
+            "caseless" | "case-insensitive" => self.case_insensitive = true,
+            "case-sensitive" => self.case_insensitive = false,
+            _ if IGNORED_OPTIONS.contains(&option) => {}
+            _ => self.warn(line, Unsupported::Option(option.into())),
+        }
+    }
+
+    /// Skips a `%{ %}` block, the first line of which was just read.
+    fn code_block(&mut self) -> Result<(), Error> {
+        let line = self.line;
+        self.warn(line, Unsupported::Code);
+
+        while let Some(text) = self.next_line() {
+            if text.starts_with("%}") {
+                return Ok(());
+            }
+        }
+        Err(self.error(line, ErrorKind::Unterminated("code block")))
+    }
+
+    /// Skips a comment starting at the start of `text`, the line just read,
+    /// and returns whether there was one.
+    fn skip_comment(&mut self, text: &str) -> Result<bool, Error> {
+        let text = text.trim_start();
+        if !text.starts_with("/*") {
+            return Ok(false);
+        }
+
+        let line = self.line;
+        if text[2..].contains("*/") {
+            return Ok(true);
+        }
+        while let Some(text) = self.next_line() {
+            if text.contains("*/") {
+                return Ok(true);
+            }
+        }
+        Err(self.error(line, ErrorKind::Unterminated("comment")))
+    }
+
+    fn rules(&mut self) -> Result<Vec<Rule>, Error> {
+        let mut rules = vec![];
+        // The start conditions of the enclosing `<SC>{` scopes
+        let mut scopes: Vec<Vec<ModeID>> = vec![];
+        // Rules whose action is `|`, waiting for the action of the next rule
+        let mut pending = vec![];
+
+        while let Some(text) = self.next_line() {
+            let line = self.line;
+
+            if text.trim_end() == "%%" {
+                break;
+            }
+            if text.trim().is_empty() || self.skip_comment(text)? {
+                continue;
+            }
+            if text.starts_with("%{") {
+                self.code_block()?;
+                continue;
+            }
+            // Rules may only be indented within scopes
+            if text.starts_with(char::is_whitespace) && scopes.is_empty() {
+                self.warn(line, Unsupported::Code);
+                continue;
+            }
+
+            let text = text.trim_start();
+            if text.trim_end() == "}" && !scopes.is_empty() {
+                scopes.pop();
+                continue;
+            }
+
+            let (mut modes, text) = self.start_conditions(line, text)?;
+            if text.trim() == "{" {
+                scopes.push(modes);
+                continue;
+            }
+            for scope in scopes.iter() {
+                modes.extend(scope);
+            }
+            if modes.is_empty() {
+                modes.push(INITIAL);
+                let inclusive = self.conditions.iter().enumerate().filter(|(_, c)| !c.1);
+                modes.extend(inclusive.map(|(i, _)| i + 1));
+            }
+            modes.sort_unstable();
+            modes.dedup();
+
+            if let Some(rest) = text.strip_prefix("<<EOF>>") {
+                self.warn(line, Unsupported::EndOfFile);
+                self.action(line, rest.trim_start())?;
+                continue;
+            }
+
+            let (pattern, trailing, rest) = self.pattern(line, text)?;
+            let rule = Rule {
+                line,
+                modes,
+                pattern,
+                trailing,
+                code: Code {
+                    kind: None,
+                    action: None,
+                },
+            };
+            match self.action(line, rest.trim_start())? {
+                Some(code) => {
+                    for mut rule in pending.drain(..).chain(Some(rule)) {
+                        rule.code = code.clone();
+                        rules.push(rule);
+                    }
+                }
+                None => pending.push(rule),
+            }
+        }
+
+        if let Some(rule) = pending.first() {
+            return Err(self.error(rule.line, ErrorKind::Syntax("| action on the last rule")));
+        }
+        Ok(rules)
+    }
+
+    /// Reads the `<SC1,SC2>` prefix of a rule, if any, returning the modes it
+    /// lists and the rest of the rule.
+    fn start_conditions<'t>(
+        &self,
+        line: usize,
+        text: &'t str,
+    ) -> Result<(Vec<ModeID>, &'t str), Error> {
+        if !text.starts_with('<') || text.starts_with("<<EOF>>") {
+            return Ok((vec![], text));
+        }
+        let end = match text.find('>') {
+            Some(end) => end,
+            None => return Err(self.error(line, ErrorKind::Unterminated("start conditions"))),
+        };
+
+        let mut modes = vec![];
+        for name in text[1..end].split(',').map(str::trim) {
+            if name == "*" {
+                modes.extend(0..=self.conditions.len());
+            } else {
+                modes.push(self.mode(line, name)?);
+            }
+        }
+
+        Ok((modes, &text[end + 1..]))
+    }
+
+    fn mode(&self, line: usize, name: &str) -> Result<ModeID, Error> {
+        if name == "INITIAL" || name == "0" {
+            return Ok(INITIAL);
+        }
+
+        match self.conditions.iter().position(|(c, _)| c == name) {
+            Some(i) => Ok(i + 1),
+            None => Err(self.error(line, ErrorKind::UndefinedStartCondition(name.into()))),
+        }
+    }
+
+    /// Translates the flex pattern at the start of `text` into the syntax of
+    /// `regex-syntax`, returning it, its trailing context if it has any, and
+    /// the text after it.
+    fn pattern<'t>(
+        &self,
+        line: usize,
+        text: &'t str,
+    ) -> Result<(String, Option<String>, &'t str), Error> {
+        let mut out = String::new();
+        let mut head = None;
+        let mut rest = "";
+        let mut chars = text.char_indices().peekable();
+
+        while let Some((i, c)) = chars.next() {
+            match c {
+                _ if c.is_whitespace() => {
+                    rest = &text[i..];
+                    break;
+                }
+                '"' => loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => push_literal(&mut out, escape(&mut chars)),
+                        Some((_, c)) => push_literal(&mut out, c),
+                        None => return Err(self.error(line, ErrorKind::Unterminated("quote"))),
+                    }
+                },
+                '[' => self.class(line, &mut chars, &mut out)?,
+                '\\' => push_literal(&mut out, escape(&mut chars)),
+                '{' => {
+                    let start = i + 1;
+                    let end = loop {
+                        match chars.next() {
+                            Some((j, '}')) => break j,
+                            Some(_) => {}
+                            None => return Err(self.error(line, ErrorKind::Unterminated("braces"))),
+                        }
+                    };
+
+                    let inner = &text[start..end];
+                    if inner.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+                        match self.definitions.get(inner) {
+                            Some(pattern) => out.push_str(pattern),
+                            None => {
+                                let name = ErrorKind::UndefinedName(inner.into());
+                                return Err(self.error(line, name));
+                            }
+                        }
+                    } else {
+                        out.push_str(&text[i..=end]);
+                    }
+                }
+                '/' if head.is_none() => head = Some(mem::take(&mut out)),
+                // Anchors are only special at the ends of a pattern
+                '^' if i == 0 => out.push('^'),
+                '$' if chars
+                    .peek()
+                    .is_none_or(|&(_, next)| next.is_whitespace() || next == '/') =>
+                {
+                    out.push('$')
+                }
+                '(' | ')' | '|' | '*' | '+' | '?' | '.' => out.push(c),
+                _ => push_literal(&mut out, c),
+            }
+        }
+
+        let (pattern, trailing) = match head {
+            Some(head) => (head, Some(out)),
+            None => (out, None),
+        };
+        if pattern.is_empty() || trailing.as_ref().is_some_and(String::is_empty) {
+            return Err(self.error(line, ErrorKind::Syntax("missing pattern")));
+        }
+        Ok((pattern, trailing, rest))
+    }
+
+    /// Translates a class whose `[` was just read.
+    fn class(
+        &self,
+        line: usize,
+        chars: &mut Peekable<CharIndices>,
+        out: &mut String,
+    ) -> Result<(), Error> {
+        out.push('[');
+        if chars.next_if(|&(_, c)| c == '^').is_some() {
+            out.push('^');
+        }
+        if chars.next_if(|&(_, c)| c == ']').is_some() {
+            out.push_str("\\]");
+        }
+
+        loop {
+            let c = match chars.next() {
+                Some((_, c)) => c,
+                None => return Err(self.error(line, ErrorKind::Unterminated("class"))),
+            };
+
+            match c {
+                ']' => {
+                    out.push(']');
+                    return Ok(());
+                }
+                '[' if chars.next_if(|&(_, c)| c == ':').is_some() => {
+                    out.push_str("[:");
+                    loop {
+                        match chars.next() {
+                            Some((_, ']')) => break,
+                            Some((_, c)) => out.push(c),
+                            None => return Err(self.error(line, ErrorKind::Unterminated("class"))),
+                        }
+                    }
+                    out.push(']');
+                }
+                '\\' => push_class_literal(out, escape(chars)),
+                // A second `-` would be a set difference in `regex-syntax`
+                '-' if !out.ends_with('-') => out.push('-'),
+                _ => push_class_literal(out, c),
+            }
+        }
+    }
+
+    /// Reads the action of a rule, following it onto the next lines while its
+    /// braces are open. Returns `None` for `|`.
+    fn action(&mut self, line: usize, text: &str) -> Result<Option<Code>, Error> {
+        if text.trim_end() == "|" {
+            return Ok(None);
+        }
+
+        let mut code = text.to_string();
+        if text.starts_with('{') {
+            code.clear();
+            let mut depth = 0;
+            let mut text = text;
+            'lines: loop {
+                let mut chars = text.chars();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '{' => depth += 1,
+                        '}' => depth -= 1,
+                        '"' | '\'' => {
+                            code.push(c);
+                            while let Some(q) = chars.next() {
+                                code.push(q);
+                                if q == '\\' {
+                                    code.extend(chars.next());
+                                } else if q == c {
+                                    break;
+                                }
+                            }
+                            continue;
+                        }
+                        _ => {}
+                    }
+
+                    if depth == 0 {
+                        break 'lines;
+                    }
+                    if depth > 1 || c != '{' {
+                        code.push(c);
+                    }
+                }
+
+                code.push('\n');
+                text = match self.next_line() {
+                    Some(text) => text,
+                    None => return Err(self.error(line, ErrorKind::Unterminated("action"))),
+                };
+            }
+        }
+
+        self.code(line, &strip_comments(&code)).map(Some)
+    }
+
+    /// Reads the statements of an action.
+    fn code(&mut self, line: usize, code: &str) -> Result<Code, Error> {
+        let mut result = Code {
+            kind: None,
+            action: None,
+        };
+        let mut ignored = vec![];
+
+        // Nested blocks are conditional, so they are left out as a whole
+        let statements = if code.contains(['{', '}']) {
+            ignored.push(code.trim().to_string());
+            vec![]
+        } else {
+            split_statements(code)
+        };
+
+        for statement in statements {
+            let call = |name: &str| {
+                let args = statement.strip_prefix(name)?;
+                if args.starts_with(|c: char| c.is_alphanumeric() || c == '_') {
+                    return None;
+                }
+                Some(unparenthesize(args.trim()))
+            };
+            if let Some(value) = call("return").filter(|v| !v.is_empty()) {
+                if result.kind.is_none() {
+                    result.kind = Some(value.to_string());
+                    continue;
+                }
+            } else if let Some(name) = call("BEGIN").filter(|n| !n.is_empty()) {
+                if result.action.is_none() {
+                    result.action = Some(Action::Switch(self.mode(line, name)?));
+                    continue;
+                }
+            } else if let Some(name) = call("yy_push_state").filter(|n| !n.is_empty()) {
+                if result.action.is_none() {
+                    result.action = Some(Action::Push(self.mode(line, name)?));
+                    continue;
+                }
+            } else if call("yy_pop_state") == Some("") && result.action.is_none() {
+                result.action = Some(Action::Pop);
+                continue;
+            }
+
+            ignored.push(statement);
+        }
+
+        if !ignored.is_empty() {
+            self.warn(line, Unsupported::Action(ignored.join("; ")));
+        }
+        Ok(result)
+    }
+
+    /// Warns about any user code after the rules.
+    fn user_code(&mut self) {
+        while let Some(text) = self.next_line() {
+            if !text.trim().is_empty() {
+                let line = self.line;
+                self.warn(line, Unsupported::UserCode);
+                return;
+            }
+        }
+    }
+
+    fn build(mut self, rules: Vec<Rule>) -> Import {
+        let mut builder = LexiconBuilder::new();
+        let mut kinds = vec![];
+        let mut lines = vec![];
+        let flags = if self.case_insensitive { "(?i)" } else { "" };
+
+        for rule in rules {
+            let pattern = format!("{}{}", flags, rule.pattern);
+            let trailing = rule.trailing.map(|t| format!("{}{}", flags, t));
+            builder = builder.modes(&rule.modes);
+
+            let kind = rule.code.kind.map(|kind| {
+                kinds.iter().position(|k| *k == kind).unwrap_or_else(|| {
+                    kinds.push(kind);
+                    kinds.len() - 1
+                })
+            });
+            builder = match (kind, trailing) {
+                (Some(kind), None) => builder.pattern(kind, &pattern),
+                (Some(kind), Some(trailing)) => {
+                    builder.pattern_with_lookahead(kind, &pattern, &trailing)
+                }
+                (None, None) => builder.skip(&pattern),
+                (None, Some(_)) => {
+                    self.warn(rule.line, Unsupported::SkippedTrailingContext);
+                    continue;
+                }
+            };
+
+            lines.push(rule.line);
+
+            if let Some(action) = rule.code.action {
+                builder = builder.action(action);
+            }
+        }
+
+        let mut modes = vec!["INITIAL".to_string()];
+        modes.extend(self.conditions.into_iter().map(|(name, _)| name));
+        self.warnings.sort_by_key(|w| w.line);
+
+        Import {
+            builder,
+            kinds,
+            modes,
+            lines,
+            warnings: self.warnings,
+        }
+    }
+}
+
+/// Reads the char escaped by a `\` that was just read, as flex does.
+fn escape(chars: &mut Peekable<CharIndices>) -> char {
+    let c = match chars.next() {
+        Some((_, c)) => c,
+        None => return '\\',
+    };
+
+    let (radix, max) = match c {
+        'n' => return '\n',
+        't' => return '\t',
+        'r' => return '\r',
+        'f' => return '\x0C',
+        'v' => return '\x0B',
+        'a' => return '\x07',
+        'b' => return '\x08',
+        '0'..='7' => (8, 3),
+        'x' if chars.peek().is_some_and(|&(_, c)| c.is_ascii_hexdigit()) => (16, 2),
+        _ => return c,
+    };
+
+    let mut value = if radix == 8 { c as u32 - '0' as u32 } else { 0 };
+    let mut digits = if radix == 8 { 1 } else { 0 };
+    while digits < max {
+        match chars.peek().and_then(|&(_, c)| c.to_digit(radix)) {
+            Some(digit) => value = value * radix + digit,
+            None => break,
+        }
+        chars.next();
+        digits += 1;
+    }
+
+    char::from_u32(value).unwrap_or('\u{FFFD}')
+}
+
+fn push_literal(out: &mut String, c: char) {
+    if "\\.+*?()|[]{}^$#&-~".contains(c) {
+        out.push('\\');
+        out.push(c);
+    } else {
+        push_char(out, c);
+    }
+}
+
+fn push_class_literal(out: &mut String, c: char) {
+    if "\\[]^-&~".contains(c) {
+        out.push('\\');
+        out.push(c);
+    } else {
+        push_char(out, c);
+    }
+}
+
+/// Pushes a char, escaping whitespace and control chars.
+fn push_char(out: &mut String, c: char) {
+    if c.is_whitespace() || c.is_control() {
+        out.push_str(&format!("\\x{{{:X}}}", c as u32));
+    } else {
+        out.push(c);
+    }
+}
+
+fn is_comment(text: &str) -> bool {
+    text.starts_with("/*") || text.starts_with("//")
+}
+
+/// Removes the comments from C code.
+fn strip_comments(code: &str) -> String {
+    let mut out = String::new();
+    let mut chars = code.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' | '\'' => {
+                out.push(c);
+                while let Some(q) = chars.next() {
+                    out.push(q);
+                    if q == '\\' {
+                        out.extend(chars.next());
+                    } else if q == c {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.next_if_eq(&'*').is_some() => {
+                while let Some(c) = chars.next() {
+                    if c == '*' && chars.next_if_eq(&'/').is_some() {
+                        break;
+                    }
+                }
+                out.push(' ');
+            }
+            '/' if chars.next_if_eq(&'/').is_some() => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+            _ => out.push(c),
+        }
+    }
+
+    out
+}
+
+/// Splits C code into its statements, ignoring `;` within quotes.
+fn split_statements(code: &str) -> Vec<String> {
+    let mut statements = vec![];
+    let mut current = String::new();
+    let mut chars = code.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ';' => statements.push(mem::take(&mut current)),
+            '"' | '\'' => {
+                current.push(c);
+                while let Some(q) = chars.next() {
+                    current.push(q);
+                    if q == '\\' {
+                        current.extend(chars.next());
+                    } else if q == c {
+                        break;
+                    }
+                }
+            }
+            _ => current.push(c),
+        }
+    }
+    statements.push(current);
+
+    statements
+        .into_iter()
+        .map(|s| s.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Removes the parentheses around the arguments of a call or a returned
+/// value.
+fn unparenthesize(text: &str) -> &str {
+    match text.strip_prefix('(').and_then(|t| t.strip_suffix(')')) {
+        Some(inner) => inner.trim(),
+        None => text,
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(not(feature = "regex-syntax"), allow(dead_code, unused_imports))]
+mod tests {
+    use super::{import, Error, ErrorKind, Import, LexiconError, Unsupported, Warning};
+    use crate::{Lexer, Next};
+
+    const CALC: &str = r#"
+/* A calculator */
+%{
+#include "parser.h"
+%}
+%option noyywrap yylineno
+%option reentrant
+%x COMMENT
+%s EXPR
+
+DIGIT    [0-9]
+NUMBER   {DIGIT}+("."{DIGIT}+)?
+
+%%
+{NUMBER}            { yylval = atof(yytext); return NUMBER; }
+"+"                 |
+"-"                 return OP;
+[a-z]+/"("          return(CALL);
+[a-z]+              return IDENT;
+"/*"                BEGIN(COMMENT);
+<COMMENT>{
+    "*/"            BEGIN INITIAL;
+    [^*]+|"*"       /* skipped */
+}
+<EXPR>";"           return ';';
+[ \t\n]+            ;
+<<EOF>>             { return 0; }
+.                   { printf("unexpected %s\n",
+                        yytext); }
+%%
+int main() { return yylex(); }
+"#;
+
+    fn tokens(input: &str) -> Vec<(String, String)> {
+        let Import { builder, kinds, .. } = import(CALC).unwrap();
+        let lexicon = builder.build().unwrap();
+
+        Lexer::new(&lexicon, input)
+            .map(|next| match next {
+                Next::Token(t) => (kinds[t.kind].clone(), t.text.to_string()),
+                Next::Error(..) => ("error".to_string(), String::new()),
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "regex-syntax")]
+    #[test]
+    fn rules() {
+        let expected = [
+            ("CALL", "max"),
+            ("IDENT", "x"),
+            ("OP", "+"),
+            ("NUMBER", "1.5"),
+            ("OP", "-"),
+            ("NUMBER", "2"),
+        ];
+        let tokens = tokens("max(x /* comment */ + 1.5 - 2");
+        let tokens = tokens.iter().map(|(k, t)| (&k[..], &t[..]));
+
+        // `(` only matches `.`, which returns nothing
+        assert!(tokens.eq(expected.iter().copied()));
+    }
+
+    #[test]
+    fn structure() {
+        let import = import(CALC).unwrap();
+
+        assert_eq!(import.kinds, vec!["NUMBER", "OP", "CALL", "IDENT", "';'"]);
+        assert_eq!(import.modes, vec!["INITIAL", "COMMENT", "EXPR"]);
+
+        let warnings = import
+            .warnings
+            .iter()
+            .map(|w| w.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            warnings,
+            vec![
+                "line 3: C code left out",
+                "line 7: unsupported option reentrant",
+                "line 15: action code left out: yylval = atof(yytext)",
+                "line 27: <<EOF>> rules are unsupported",
+                "line 28: action code left out: printf(\"unexpected %s\\n\", yytext)",
+                "line 31: user code left out",
+            ]
+        );
+    }
+
+    #[cfg(feature = "regex-syntax")]
+    #[test]
+    fn patterns() {
+        let pattern = |flex: &str| {
+            let source = format!("D [0-9]\n%%\n{} return X;", flex);
+            let import = import(&source).unwrap();
+            let lexicon = import.builder.build().unwrap();
+            move |input: &str| {
+                Lexer::new(&lexicon, input)
+                    .map(|next| match next {
+                        Next::Token(t) => t.text.len(),
+                        Next::Error(..) => 0,
+                    })
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(pattern(r#""a+b""#)("a+b"), vec![3]);
+        assert_eq!(pattern(r"\b\x41\101\.")("\x08AA."), vec![4]);
+        assert_eq!(pattern(r"[]a-]+")("]a-"), vec![3]);
+        assert_eq!(pattern(r"[+--]+")("+,-"), vec![3]);
+        assert_eq!(pattern(r"[[:digit:]&~]+")("1&~"), vec![3]);
+        assert_eq!(pattern(r"{D}{2}")("12"), vec![2]);
+        assert_eq!(pattern(r"a^$b")("a^$b"), vec![4]);
+        assert_eq!(pattern(r"^a$")("a\na"), vec![1, 0, 1]);
+        assert_eq!(pattern(r#"[ "]"\t""#)("\"\t"), vec![2]);
+        assert_eq!(pattern(r"a/b")("ab"), vec![1, 0]);
+    }
+
+    #[cfg(feature = "regex-syntax")]
+    #[test]
+    fn start_conditions() {
+        let source = r#"
+%s INCL
+%x EXCL
+%option case-insensitive
+%%
+a       return A;
+<INCL>b return B;
+<EXCL>c yy_pop_state();
+<*>d    return D; yy_push_state(EXCL);
+e       BEGIN(INCL);
+"#;
+        let Import {
+            builder,
+            kinds,
+            warnings,
+            ..
+        } = import(source).unwrap();
+        let lexicon = builder.build().unwrap();
+
+        let tokens = Lexer::new(&lexicon, "EaBDCa")
+            .map(|next| match next {
+                Next::Token(t) => kinds[t.kind].as_str(),
+                Next::Error(..) => "error",
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(tokens, vec!["A", "B", "D", "A"]);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn errors() {
+        let error = |source: &str| import(source).err().unwrap();
+
+        assert_eq!(
+            error("D [0-9]\n"),
+            Error {
+                line: 1,
+                kind: ErrorKind::MissingRules
+            }
+        );
+        assert_eq!(
+            error("%%\n\na{E} return A;"),
+            Error {
+                line: 3,
+                kind: ErrorKind::UndefinedName("E".into())
+            }
+        );
+        assert_eq!(
+            error("%%\n<S>a return A;"),
+            Error {
+                line: 2,
+                kind: ErrorKind::UndefinedStartCondition("S".into())
+            }
+        );
+        assert_eq!(
+            error("%%\na { return A;\n\n"),
+            Error {
+                line: 2,
+                kind: ErrorKind::Unterminated("action")
+            }
+        );
+        assert_eq!(
+            error("%%\n[a-z return A;"),
+            Error {
+                line: 2,
+                kind: ErrorKind::Unterminated("class")
+            }
+        );
+        assert_eq!(
+            error("%%\na |\n"),
+            Error {
+                line: 2,
+                kind: ErrorKind::Syntax("| action on the last rule")
+            }
+        );
+    }
+
+    #[cfg(feature = "regex-syntax")]
+    #[test]
+    fn build_errors() {
+        let mut import = import("%%\na/b ;\n[a-z]+ return ID;\na{2,1} return A;\n").unwrap();
+        assert_eq!(import.lines, vec![3, 4]);
+
+        import.builder = import.builder.literal(9, "");
+        let e = import.build().err().unwrap();
+        assert_eq!(e.line, 4);
+        assert!(matches!(
+            e.kind,
+            ErrorKind::Lexicon(LexiconError::InvalidRegex(_))
+        ));
+        assert!(e.to_string().starts_with("line 4: "));
+
+        let mut import = super::import("%%\n[a-z]+ return ID;\n").unwrap();
+        import.builder = import.builder.literal(9, "");
+        assert_eq!(
+            import.build().err().unwrap(),
+            Error {
+                line: 0,
+                kind: ErrorKind::Lexicon(LexiconError::EmptyLiteral(9))
+            }
+        );
+    }
+
+    #[cfg(feature = "regex-syntax")]
+    #[test]
+    fn skipped_trailing_context() {
+        let import = import("%%\na/b ;\nb return B;").unwrap();
+        let lexicon = import.builder.build().unwrap();
+
+        assert_eq!(Lexer::new(&lexicon, "b").count(), 1);
+        assert_eq!(
+            import.warnings,
+            vec![Warning {
+                line: 2,
+                unsupported: Unsupported::SkippedTrailingContext
+            }]
+        );
+    }
+}
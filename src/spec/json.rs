@@ -0,0 +1,739 @@
+//! Builds lexicons from JSON documents listing their tokens, so that they can
+//! be changed without recompiling:
+//!
+//! ```
+//! use lexer::LexiconBuilder;
+//!
+//! let spec = LexiconBuilder::from_spec(r##"{
+//!     "ignore": " \t\n",
+//!     "tokens": [
+//!         { "name": "if", "kind": "literal", "value": "if", "id": 0 },
+//!         { "name": "ident", "kind": "pattern", "value": "[a-z]+", "id": 1 },
+//!         { "name": "comment", "kind": "skip", "value": "#[^\n]*" }
+//!     ]
+//! }"##)
+//! .unwrap();
+//!
+//! # #[cfg(feature = "regex-syntax")]
+//! let lexicon = spec.build().unwrap();
+//! ```
+//!
+//! Tokens are added in order. Each has a `name`, which errors refer to, a
+//! `kind` of `literal`, `pattern` or `skip`, and a `value` holding the literal
+//! or pattern. Literals and patterns also have an `id`, the kind of their
+//! tokens, and any token may have a `precedence`, as set with
+//! `LexiconBuilder::precedence`. The optional `ignore` holds the chars to
+//! ignore.
+//!
+//! Literals and patterns are only compiled by `Spec::build`, whose errors
+//! point at the `value` of the token they are in. The builder and the location
+//! of each of its rules are public, so that rules such as those with actions
+//! can be added to it before building.
+//!
+//! Documents are read as JSON only; there is no TOML or other format.
+
+use std::fmt;
+
+use crate::lexicon::{Error as LexiconError, Lexicon, LexiconBuilder};
+
+/// The tokens of a document, ready to be built.
+pub struct Spec {
+    /// The rules of the tokens, in order.
+    pub builder: LexiconBuilder,
+    /// The location of each rule of `builder`, by index.
+    pub locations: Vec<Location>,
+}
+
+/// The line and column of the value of a token, and the token's name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+    pub token: String,
+}
+
+/// Why a document could not be read, and where.
+#[derive(Debug)]
+pub struct Error {
+    pub line: usize,
+    pub column: usize,
+    /// The name of the token the error is in, if it has one.
+    pub token: Option<String>,
+    pub kind: ErrorKind,
+}
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    /// The document is not valid JSON.
+    Syntax(&'static str),
+    MissingField(&'static str),
+    UnknownField(String),
+    DuplicateField(String),
+    /// A field of the wrong type, or with a value out of range.
+    InvalidField(&'static str),
+    /// A literal or pattern that could not be built.
+    Lexicon(LexiconError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: ", self.line, self.column)?;
+        if let Some(token) = &self.token {
+            write!(f, "token {:?}: ", token)?;
+        }
+
+        match &self.kind {
+            ErrorKind::Syntax(message) => write!(f, "{}", message),
+            ErrorKind::MissingField(field) => write!(f, "missing field {:?}", field),
+            ErrorKind::UnknownField(field) => write!(f, "unknown field {:?}", field),
+            ErrorKind::DuplicateField(field) => write!(f, "duplicate field {:?}", field),
+            ErrorKind::InvalidField(field) => write!(f, "invalid {:?}", field),
+            ErrorKind::Lexicon(LexiconError::InvalidRegex(e)) => write!(f, "{}", e),
+            ErrorKind::Lexicon(LexiconError::EmptyLiteral(_)) => write!(f, "empty literal"),
+            ErrorKind::Lexicon(LexiconError::EmptyTrailingContextHead(_)) => {
+                write!(
+                    f,
+                    "pattern before the trailing context matches the empty string"
+                )
+            }
+            ErrorKind::Lexicon(LexiconError::EmptyShortestPattern(_)) => {
+                write!(f, "shortest-match pattern matches the empty string")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Spec {
+    /// Builds DFA states on demand, as `LexiconBuilder::lazy_dfa` does.
+    pub fn lazy_dfa(mut self, cache_size: usize) -> Self {
+        self.builder = self.builder.lazy_dfa(cache_size);
+
+        self
+    }
+
+    /// Simulates the NFA, as `LexiconBuilder::pike_vm` does.
+    pub fn pike_vm(mut self) -> Self {
+        self.builder = self.builder.pike_vm();
+
+        self
+    }
+
+    /// Builds the lexicon, with errors pointing at the token they are in.
+    /// Errors in rules added to `builder` after those of the document are at
+    /// line and column 0, with no token.
+    pub fn build(self) -> Result<Lexicon, Error> {
+        let locations = self.locations;
+        self.builder
+            .build_with(false)
+            .map_err(|(rule, e)| match locations.get(rule) {
+                Some(location) => Error {
+                    line: location.line,
+                    column: location.column,
+                    token: Some(location.token.clone()),
+                    kind: ErrorKind::Lexicon(e),
+                },
+                None => Error {
+                    line: 0,
+                    column: 0,
+                    token: None,
+                    kind: ErrorKind::Lexicon(e),
+                },
+            })
+    }
+}
+
+impl LexiconBuilder {
+    /// Reads the tokens of a JSON document, as described in
+    /// `lexer::spec::json`.
+    pub fn from_spec(spec: &str) -> Result<Spec, Error> {
+        let mut parser = Parser {
+            source: spec,
+            pos: 0,
+        };
+        let document = parser.document()?;
+        let fields = match &document.kind {
+            Kind::Object(fields) => fields,
+            _ => {
+                return Err(parser.error(&document, None, ErrorKind::Syntax("expected an object")))
+            }
+        };
+
+        let mut ignore = None;
+        let mut tokens = None;
+        for (key, value) in fields.iter() {
+            let field = match key.as_str() {
+                "ignore" => &mut ignore,
+                "tokens" => &mut tokens,
+                _ => return Err(parser.error(value, None, ErrorKind::UnknownField(key.clone()))),
+            };
+            if field.replace(value).is_some() {
+                return Err(parser.error(value, None, ErrorKind::DuplicateField(key.clone())));
+            }
+        }
+
+        let mut builder = LexiconBuilder::new();
+        if let Some(ignore) = ignore {
+            builder = builder.ignore_chars(parser.string(ignore, None, "ignore")?);
+        }
+
+        let tokens = match tokens.map(|t| &t.kind) {
+            Some(Kind::Array(tokens)) => tokens,
+            Some(_) => {
+                let tokens = tokens.unwrap();
+                return Err(parser.error(tokens, None, ErrorKind::InvalidField("tokens")));
+            }
+            None => {
+                return Err(parser.error(&document, None, ErrorKind::MissingField("tokens")));
+            }
+        };
+        let mut locations = vec![];
+        for token in tokens.iter() {
+            let (next, location) = parser.token(builder, token)?;
+            builder = next;
+            locations.push(location);
+        }
+
+        Ok(Spec { builder, locations })
+    }
+}
+
+/// A JSON value, along with the offset it starts at.
+struct Value {
+    offset: usize,
+    kind: Kind,
+}
+
+enum Kind {
+    /// `true`, `false` or `null`, which no field takes.
+    Keyword,
+    /// The text of a number, parsed once the type it is read as is known.
+    Number(String),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+/// Values nested deeper than this are an error rather than a stack overflow.
+const MAX_DEPTH: usize = 64;
+
+struct Parser<'s> {
+    source: &'s str,
+    pos: usize,
+}
+
+impl<'s> Parser<'s> {
+    fn error(&self, value: &Value, token: Option<&str>, kind: ErrorKind) -> Error {
+        self.error_at(value.offset, token, kind)
+    }
+
+    fn error_at(&self, offset: usize, token: Option<&str>, kind: ErrorKind) -> Error {
+        let (line, column) = self.position(offset);
+
+        Error {
+            line,
+            column,
+            token: token.map(String::from),
+            kind,
+        }
+    }
+
+    /// Returns the line and column of `offset`, both starting at 1.
+    fn position(&self, offset: usize) -> (usize, usize) {
+        let before = &self.source[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+
+        (
+            before.matches('\n').count() + 1,
+            before[line_start..].chars().count() + 1,
+        )
+    }
+
+    fn syntax(&self, message: &'static str) -> Error {
+        self.error_at(self.pos, None, ErrorKind::Syntax(message))
+    }
+
+    /// Adds the rule of an entry in `tokens` to `builder`, returning it along
+    /// with the location of the entry's value.
+    fn token(
+        &self,
+        builder: LexiconBuilder,
+        token: &Value,
+    ) -> Result<(LexiconBuilder, Location), Error> {
+        let fields = match &token.kind {
+            Kind::Object(fields) => fields,
+            _ => return Err(self.error(token, None, ErrorKind::InvalidField("tokens"))),
+        };
+
+        let (mut name, mut kind, mut value, mut id, mut precedence) =
+            (None, None, None, None, None);
+        for (key, field) in fields.iter() {
+            let slot = match key.as_str() {
+                "name" => &mut name,
+                "kind" => &mut kind,
+                "value" => &mut value,
+                "id" => &mut id,
+                "precedence" => &mut precedence,
+                _ => return Err(self.error(field, None, ErrorKind::UnknownField(key.clone()))),
+            };
+            if slot.replace(field).is_some() {
+                return Err(self.error(field, None, ErrorKind::DuplicateField(key.clone())));
+            }
+        }
+
+        let missing = |field| self.error(token, None, ErrorKind::MissingField(field));
+        let name = self.string(name.ok_or_else(|| missing("name"))?, None, "name")?;
+        let token_name = Some(name);
+        let missing = |field| self.error(token, token_name, ErrorKind::MissingField(field));
+
+        let kind_value = kind.ok_or_else(|| missing("kind"))?;
+        let kind = self.string(kind_value, token_name, "kind")?;
+        if !["literal", "pattern", "skip"].contains(&kind) {
+            return Err(self.error(kind_value, token_name, ErrorKind::InvalidField("kind")));
+        }
+        let value = value.ok_or_else(|| missing("value"))?;
+        let source = self.string(value, token_name, "value")?;
+        let id = match (kind, id) {
+            ("skip", Some(id)) => {
+                return Err(self.error(id, token_name, ErrorKind::InvalidField("id")))
+            }
+            ("skip", None) => None,
+            (_, Some(id)) => Some(self.number::<usize>(id, token_name, "id")?),
+            (_, None) => return Err(missing("id")),
+        };
+
+        let builder = match (kind, id) {
+            ("literal", Some(id)) => builder.literal(id, source),
+            ("pattern", Some(id)) => builder.pattern(id, source),
+            ("skip", None) => builder.skip(source),
+            _ => unreachable!(),
+        };
+        let builder = match precedence {
+            Some(precedence) => {
+                builder.precedence(self.number::<u8>(precedence, token_name, "precedence")?)
+            }
+            None => builder,
+        };
+
+        let (line, column) = self.position(value.offset);
+        let location = Location {
+            line,
+            column,
+            token: name.to_string(),
+        };
+
+        Ok((builder, location))
+    }
+
+    fn string<'v>(
+        &self,
+        value: &'v Value,
+        token: Option<&str>,
+        field: &'static str,
+    ) -> Result<&'v str, Error> {
+        match &value.kind {
+            Kind::String(s) => Ok(s),
+            _ => Err(self.error(value, token, ErrorKind::InvalidField(field))),
+        }
+    }
+
+    /// Reads a number as a `T`, which must hold it exactly.
+    fn number<T: std::str::FromStr>(
+        &self,
+        value: &Value,
+        token: Option<&str>,
+        field: &'static str,
+    ) -> Result<T, Error> {
+        match &value.kind {
+            Kind::Number(n) => n.parse().ok(),
+            _ => None,
+        }
+        .ok_or_else(|| self.error(value, token, ErrorKind::InvalidField(field)))
+    }
+
+    /// Parses the whole source as a single value.
+    fn document(&mut self) -> Result<Value, Error> {
+        let value = self.value(0)?;
+        self.whitespace();
+        if self.pos < self.source.len() {
+            return Err(self.syntax("expected the end of the document"));
+        }
+
+        Ok(value)
+    }
+
+    fn whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.source.as_bytes().get(self.pos).copied()
+    }
+
+    /// Consumes `byte` if it comes next, after any whitespace.
+    fn eat(&mut self, byte: u8) -> bool {
+        self.whitespace();
+        if self.peek() == Some(byte) {
+            self.pos += 1;
+            return true;
+        }
+
+        false
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, Error> {
+        if depth > MAX_DEPTH {
+            return Err(self.syntax("too deeply nested"));
+        }
+
+        self.whitespace();
+        let offset = self.pos;
+        let kind = match self.peek() {
+            Some(b'{') => {
+                self.pos += 1;
+                let mut fields = vec![];
+                if !self.eat(b'}') {
+                    loop {
+                        self.whitespace();
+                        if self.peek() != Some(b'"') {
+                            return Err(self.syntax("expected a string"));
+                        }
+                        let key = self.string_literal()?;
+                        if !self.eat(b':') {
+                            return Err(self.syntax("expected `:`"));
+                        }
+                        fields.push((key, self.value(depth + 1)?));
+
+                        if self.eat(b'}') {
+                            break;
+                        }
+                        if !self.eat(b',') {
+                            return Err(self.syntax("expected `,` or `}`"));
+                        }
+                    }
+                }
+                Kind::Object(fields)
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                let mut values = vec![];
+                if !self.eat(b']') {
+                    loop {
+                        values.push(self.value(depth + 1)?);
+
+                        if self.eat(b']') {
+                            break;
+                        }
+                        if !self.eat(b',') {
+                            return Err(self.syntax("expected `,` or `]`"));
+                        }
+                    }
+                }
+                Kind::Array(values)
+            }
+            Some(b'"') => Kind::String(self.string_literal()?),
+            Some(b'-') | Some(b'0'..=b'9') => Kind::Number(self.number_literal()?),
+            _ => {
+                let rest = &self.source[self.pos..];
+                match ["true", "false", "null"]
+                    .iter()
+                    .find(|k| rest.starts_with(*k))
+                {
+                    Some(keyword) => self.pos += keyword.len(),
+                    None => return Err(self.syntax("expected a value")),
+                }
+                Kind::Keyword
+            }
+        };
+
+        Ok(Value { offset, kind })
+    }
+
+    /// Parses the string starting at the `"` that comes next.
+    fn string_literal(&mut self) -> Result<String, Error> {
+        let mut out = String::new();
+        let mut chars = self.source[self.pos + 1..].char_indices();
+
+        loop {
+            let (i, c) = match chars.next() {
+                Some(next) => next,
+                None => {
+                    self.pos = self.source.len();
+                    return Err(self.syntax("unterminated string"));
+                }
+            };
+
+            match c {
+                '"' => {
+                    self.pos += i + 2;
+                    return Ok(out);
+                }
+                '\\' => {
+                    let c = match chars.next().map(|(_, c)| c) {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\x08',
+                        Some('f') => '\x0C',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => {
+                            let c = match hex(&mut chars) {
+                                // A surrogate pair encodes a char outside
+                                // the Basic Multilingual Plane
+                                Some(high @ 0xD800..=0xDBFF)
+                                    if chars.as_str().starts_with("\\u") =>
+                                {
+                                    chars.nth(1);
+                                    match hex(&mut chars) {
+                                        Some(low @ 0xDC00..=0xDFFF) => char::from_u32(
+                                            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00),
+                                        ),
+                                        _ => None,
+                                    }
+                                }
+                                Some(unit) => char::from_u32(unit),
+                                None => None,
+                            };
+                            match c {
+                                Some(c) => c,
+                                None => {
+                                    self.pos += i + 1;
+                                    return Err(self.syntax("invalid unicode escape"));
+                                }
+                            }
+                        }
+                        _ => {
+                            self.pos += i + 1;
+                            return Err(self.syntax("invalid escape"));
+                        }
+                    };
+                    out.push(c);
+                }
+                c if (c as u32) < 0x20 => {
+                    self.pos += i + 1;
+                    return Err(self.syntax("control character in string"));
+                }
+                c => out.push(c),
+            }
+        }
+    }
+
+    /// Parses the number that comes next, checking that it is valid JSON.
+    fn number_literal(&mut self) -> Result<String, Error> {
+        let start = self.pos;
+        let digits = |parser: &mut Self| {
+            let from = parser.pos;
+            while let Some(b'0'..=b'9') = parser.peek() {
+                parser.pos += 1;
+            }
+            parser.pos > from
+        };
+
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        let leading_zero = self.peek() == Some(b'0');
+        let int_start = self.pos;
+        if !digits(self) || (leading_zero && self.pos - int_start > 1) {
+            return Err(self.error_at(start, None, ErrorKind::Syntax("invalid number")));
+        }
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            if !digits(self) {
+                return Err(self.error_at(start, None, ErrorKind::Syntax("invalid number")));
+            }
+        }
+        if let Some(b'e') | Some(b'E') = self.peek() {
+            self.pos += 1;
+            if let Some(b'+') | Some(b'-') = self.peek() {
+                self.pos += 1;
+            }
+            if !digits(self) {
+                return Err(self.error_at(start, None, ErrorKind::Syntax("invalid number")));
+            }
+        }
+
+        Ok(self.source[start..self.pos].to_string())
+    }
+}
+
+/// Reads the four hex digits of a `\u` escape.
+fn hex(chars: &mut std::str::CharIndices) -> Option<u32> {
+    let digits = chars.as_str().get(..4)?;
+    if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    chars.nth(3);
+    u32::from_str_radix(digits, 16).ok()
+}
+
+#[cfg(test)]
+#[cfg_attr(not(feature = "regex-syntax"), allow(dead_code, unused_imports))]
+mod tests {
+    use super::{Error, ErrorKind, Location, Spec};
+    use crate::{Lexer, LexiconBuilder, LexiconBuilderError, Next};
+
+    fn error(spec: &str) -> Error {
+        LexiconBuilder::from_spec(spec)
+            .and_then(Spec::build)
+            .err()
+            .unwrap()
+    }
+
+    #[cfg(feature = "regex-syntax")]
+    #[test]
+    fn tokens() {
+        let spec = r##"{
+            "ignore": " \t",
+            "tokens": [
+                { "name": "ident", "kind": "pattern", "value": "[a-z]+", "id": 0 },
+                { "name": "if", "kind": "literal", "value": "if", "id": 1 },
+                { "name": "else", "kind": "literal", "value": "else", "id": 2,
+                  "precedence": 0 },
+                { "name": "comment", "kind": "skip", "value": "#[^\n]*" },
+                { "name": "emoji", "kind": "literal", "value": "\ud83d\ude00", "id": 3 }
+            ]
+        }"##;
+        let lexicon = LexiconBuilder::from_spec(spec).unwrap().build().unwrap();
+
+        let kinds = Lexer::new(&lexicon, "if else # if\n\u{1F600}")
+            .map(|next| match next {
+                Next::Token(token) => Some(token.kind),
+                Next::Error(..) => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(kinds, vec![Some(1), Some(0), None, Some(3)]);
+    }
+
+    #[cfg(feature = "regex-syntax")]
+    #[test]
+    fn locations() {
+        let e = error("{\n  \"tokens\": [\n    { \"name\": \"a\", \"kind\": \"pattern\", \"value\": \"[a\", \"id\": 0 }\n  ]\n}");
+        assert_eq!((e.line, e.column), (3, 48));
+        assert_eq!(e.token.as_deref(), Some("a"));
+        assert!(matches!(
+            e.kind,
+            ErrorKind::Lexicon(LexiconBuilderError::InvalidRegex(_))
+        ));
+
+        let e = error(
+            r#"{"tokens": [
+                {"name": "a", "kind": "skip", "value": "a"},
+                {"name": "b", "kind": "pattern", "value": "b{2,1}", "id": 1}
+            ]}"#,
+        );
+        assert_eq!((e.line, e.column), (3, 59));
+        assert_eq!(e.token.as_deref(), Some("b"));
+
+        let e = error(r#"{"tokens": [{"name": "é", "kind": "literal", "value": "", "id": 7}]}"#);
+        assert_eq!((e.line, e.column), (1, 55));
+        assert!(matches!(
+            e.kind,
+            ErrorKind::Lexicon(LexiconBuilderError::EmptyLiteral(7))
+        ));
+        assert_eq!(
+            e.to_string(),
+            "line 1, column 55: token \"é\": empty literal"
+        );
+    }
+
+    #[test]
+    fn parts() {
+        let mut spec = LexiconBuilder::from_spec(
+            r#"{"tokens": [
+                {"name": "a", "kind": "literal", "value": "a", "id": 0}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            spec.locations,
+            vec![Location {
+                line: 2,
+                column: 59,
+                token: "a".to_string()
+            }]
+        );
+
+        spec.builder = spec.builder.pattern(1, "[");
+        let e = spec.build().err().unwrap();
+        assert_eq!((e.line, e.column, e.token), (0, 0, None));
+    }
+
+    #[test]
+    fn fields() {
+        let token = |fields: &str| {
+            let e = error(&format!(r#"{{"tokens": [{{"name": "t", {}}}]}}"#, fields));
+            (e.column, e.kind)
+        };
+
+        assert!(matches!(
+            token(r#""kind": "literal", "value": "a""#),
+            (13, ErrorKind::MissingField("id"))
+        ));
+        assert!(matches!(
+            token(r#""kind": "skip", "value": "a", "id": 1"#),
+            (63, ErrorKind::InvalidField("id"))
+        ));
+        assert!(matches!(
+            token(r#""kind": "regex", "value": "a", "id": 1"#),
+            (35, ErrorKind::InvalidField("kind"))
+        ));
+        assert!(matches!(
+            token(r#""kind": "pattern", "value": "a", "id": -1"#),
+            (66, ErrorKind::InvalidField("id"))
+        ));
+        assert!(matches!(
+            token(r#""kind": "pattern", "value": "a", "id": 1, "precedence": 256"#),
+            (83, ErrorKind::InvalidField("precedence"))
+        ));
+        assert!(matches!(
+            token(r#""kind": "pattern", "kind": "skip", "value": "a""#),
+            (54, ErrorKind::DuplicateField(_))
+        ));
+        assert!(matches!(
+            token(r#""kind": "pattern", "value": "a", "id": 1, "ids": 2"#),
+            (76, ErrorKind::UnknownField(_))
+        ));
+
+        assert!(matches!(
+            error("{}").kind,
+            ErrorKind::MissingField("tokens")
+        ));
+        assert!(matches!(error("[]").kind, ErrorKind::Syntax(_)));
+        assert!(matches!(
+            error(r#"{"tokens": [{"kind": "skip"}]}"#).kind,
+            ErrorKind::MissingField("name")
+        ));
+    }
+
+    #[test]
+    fn syntax() {
+        let cases = [
+            ("", 1, 1),
+            ("{\"tokens\": []", 1, 14),
+            ("{\"tokens\" []}", 1, 11),
+            ("{\"tokens\": [],}", 1, 15),
+            ("{\"tokens\": [01]}", 1, 13),
+            ("{\"tokens\": [\"\\x\"]}", 1, 14),
+            ("{\"tokens\": [\"\\ud800\"]}", 1, 14),
+            ("{\"tokens\": [\"a\nb\"]}", 1, 15),
+            ("{\"tokens\": []} []", 1, 16),
+            ("{\"tokens\": [tru]}", 1, 13),
+        ];
+
+        for &(spec, line, column) in cases.iter() {
+            let e = error(spec);
+            assert!(matches!(e.kind, ErrorKind::Syntax(_)), "{:?}", spec);
+            assert_eq!((e.line, e.column), (line, column), "{:?}", spec);
+        }
+
+        let nested = format!("{}{}", "[".repeat(1000), "]".repeat(1000));
+        assert!(matches!(error(&nested).kind, ErrorKind::Syntax(_)));
+    }
+}
@@ -0,0 +1,5 @@
+//! Builds lexicons from token lists and from the grammars of other lexer
+//! generators.
+
+pub mod flex;
+pub mod json;